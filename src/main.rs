@@ -1,43 +1,200 @@
-mod mem;
-mod sim;
-use sim::check::{ValueType, DataType, Simulator};
-use sim::pointer::Pointer;
-mod proc;
+use rust_vmem::sim::check::Simulator;
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::script::Interpreter;
+use rust_vmem::sim::trace::{self, Trace, TraceFormat};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::process;
 
-fn main() {
-    let mut sim = Simulator::begin(true);
+const USAGE: &str = "usage: rust-vmem [-c CONFIG] [SCRIPT]
+       rust-vmem CONFIG.conf
+       rust-vmem [-c CONFIG] -t TRACE [-f native|lackey|din] [--compare | --sweep MIN-MAX[/STEP]] [-o CSV] [-w WS.csv]";
 
-    let mut x = ValueType::Zero;
-    let ptr_x = Pointer::new(&mut x);
+fn main() {
+    let mut config_path = None;
+    let mut script = None;
+    let mut trace = None;
+    let mut format = None;
+    let mut per_line = None;
+    let mut compare = false;
+    let mut sweep = None;
+    let mut ws = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => config_path = Some(args.next().unwrap_or_else(|| usage())),
+            "-t" | "--trace" => trace = Some(args.next().unwrap_or_else(|| usage())),
+            "-f" | "--format" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                format = Some(name.parse::<TraceFormat>().unwrap_or_else(|_| usage()));
+            },
+            "-o" | "--per-line" => per_line = Some(args.next().unwrap_or_else(|| usage())),
+            "--compare" => compare = true,
+            "-w" | "--working-sets" => ws = Some(args.next().unwrap_or_else(|| usage())),
+            "--sweep" => {
+                let range = args.next().unwrap_or_else(|| usage());
+                sweep = Some(trace::parse_frames(&range).unwrap_or_else(|| usage()));
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            // a lone config file, as the binary used to take
+            _ if arg.ends_with(".conf") && config_path.is_none() => config_path = Some(arg),
+            _ if script.is_none() => script = Some(arg),
+            _ => usage(),
+        }
+    }
 
-    sim.register(ptr_x);
-    sim.write(ptr_x, ValueType::SignedInt(-2));
-    match sim.read(ptr_x, DataType::SignedInt) {
-        Some(value) => println!("Value of x: {}", value.get_value() as isize),
-        None => {}
+    // an optional config file picks the machine to simulate
+    let config = match config_path {
+        Some(path) => match SimulatorConfig::from_file(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => SimulatorConfig::new(),
     };
-    
-    let mut y = ValueType::Zero;
-    let ptr_y = Pointer::new(&mut y);
-
-    sim.write(ptr_y, ValueType::UnsignedInt(2));
-    match sim.read(ptr_y, DataType::UnsignedInt) {
-        Some(value) => println!("Value of y: {}", value.get_value()),
-        None => {}
+    let mut sim = match Simulator::begin(config) {
+        Ok(sim) => sim,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
     };
+    if let Some(path) = trace {
+        if let Some(frames) = sweep {
+            sweep_frames(&sim, &path, format, frames, per_line);
+        } else if compare {
+            compare_policies(&sim, &path, format);
+        } else {
+            replay(&mut sim, &path, format, per_line);
+        }
+        if let Some(csv) = ws {
+            if let Err(err) = sim.export_working_sets(&csv) {
+                eprintln!("{}: {}", csv, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    let mut interp = Interpreter::new(sim);
 
-    sim.fork();
-    sim.write(ptr_x, ValueType::UnsignedInt(3));
-    match sim.read(ptr_x, DataType::UnsignedInt) {
-        Some(value) => println!("Value of x: {}", value.get_value()),
-        None => {}
+    // commands come from the script, or from stdin with a prompt when
+    // someone is typing them
+    let ok = match script {
+        Some(path) => match File::open(&path) {
+            Ok(file) => run(&mut interp, BufReader::new(file), &path, false),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        None => {
+            let stdin = io::stdin();
+            let interactive = stdin.is_terminal();
+            run(&mut interp, stdin.lock(), "stdin", interactive)
+        }
     };
-    match sim.read(ptr_y, DataType::SignedInt) {
-        Some(value) => println!("Value of y: {}", value.get_value()),
-        None => {}
+    if !ok {
+        process::exit(1);
+    }
+}
+
+// Replays a trace and prints what it did, along with the machine's stats.
+fn replay(sim: &mut Simulator, path: &str, format: Option<TraceFormat>, per_line: Option<String>) {
+    let report = match sim.replay(path, format) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
     };
+    print!("{}", report);
+    print!("{}", sim.meminfo());
+    if let Some(csv) = per_line {
+        let written = File::create(&csv).and_then(|mut file| report.write_csv(&mut file));
+        if let Err(err) = written {
+            eprintln!("{}: {}", csv, err);
+            process::exit(1);
+        }
+    }
+}
 
+// Replays a trace under every replacement policy and OPT on fresh copies
+// of the machine.
+fn compare_policies(sim: &Simulator, path: &str, format: Option<TraceFormat>) {
+    let compared = Trace::open(path, format).and_then(|trace| trace::compare(sim.config(), &trace));
+    match compared {
+        Ok(comparison) => print!("{}", comparison),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+// Compares the policies at every frame count, printing the fault curves
+// and optionally writing them out as CSV.
+fn sweep_frames(sim: &Simulator, path: &str, format: Option<TraceFormat>, frames: Vec<u32>, csv: Option<String>) {
+    let swept = Trace::open(path, format).and_then(|trace| trace::sweep(sim.config(), &trace, frames));
+    let sweep = match swept {
+        Ok(sweep) => sweep,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    print!("{}", sweep);
+    if let Some(csv) = csv {
+        let written = File::create(&csv).and_then(|mut file| sweep.write_csv(&mut file));
+        if let Err(err) = written {
+            eprintln!("{}: {}", csv, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
 
-    println!();
-    sim.print();
+// Runs every line of `input`, reporting errors and carrying on with the
+// next line. Returns false if a command in a script failed.
+fn run<R: BufRead>(interp: &mut Interpreter, input: R, name: &str, interactive: bool) -> bool {
+    let mut ok = true;
+    let mut lines = input.lines();
+    for n in 1.. {
+        if interactive {
+            print!("vmem> ");
+            let _ = io::stdout().flush();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("{}: {}", name, err);
+                return false;
+            },
+            None => break
+        };
+        if let Err(err) = interp.exec(&line) {
+            if interactive {
+                eprintln!("error: {}", err);
+            } else {
+                eprintln!("{}:{}: {}", name, n, err);
+                ok = false;
+            }
+        }
+        if interp.finished() {
+            break;
+        }
+    }
+    if interactive {
+        println!();
+    }
+    ok
 }
@@ -1,30 +1,31 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::vec::Vec;
 
-static ZERO_PAGE: Page = Page {
-    data: [0; 4096],
-    ref_count: 0,
-    ppn: 0
-};
+use super::mmap::MappedFile;
+use super::ptable::{Flag, Geometry, PTE};
+use super::replace::ReplacementPolicy;
+use super::swap::SwapDevice;
+use super::tlb::Tlb;
+use crate::sim::error::SimError;
+use crate::sim::event::{EventKind, EventLog};
+use crate::sim::stats::Walks;
 
-static mut MEM: Memory = Memory {
-    free_list: Vec::new(),
-    used_list: Vec::new(),
-    zero_page: &ZERO_PAGE
-};
-
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Page {
-    data: [u8; 4096],
+    data: Vec<u8>,
     ref_count: usize,
     ppn: u32,
 }
 
 impl Page {
-    pub(crate) fn new(ppn: u32) -> Self {
+    pub(crate) fn new(ppn: u32, size: usize) -> Self {
         Self {
-            data: [0; 4096],
+            data: vec![0; size],
             ref_count: 0,
-            ppn: ppn
+            ppn
         }
     }
 
@@ -44,19 +45,19 @@ impl Page {
         let size = std::mem::size_of::<T>();
         let s = index - index % size;
         let e = s + size;
-        let mut i = 0;
-        for idx in s..e {
-            if i < data.len() {
-                self.data[idx] = data[i];
-                i += 1;
-            }
+        for (idx, &byte) in (s..e).zip(data.iter()) {
+            self.data[idx] = byte;
         }
     }
 
     pub fn ppn(&self) -> u32 { self.ppn }
 
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
     fn zero(&mut self) {
-        self.write::<[u8; 4096]>(0, &[0; 4096]);
+        self.data.iter_mut().for_each(|byte| *byte = 0);
     }
 
     pub fn ref_count(&self) -> usize {
@@ -72,97 +73,914 @@ impl Page {
     }
 
     pub fn copy(&mut self, other: &Page) {
-        self.write::<[u8; 4096]>(0, &other.data);
+        self.data.copy_from_slice(&other.data);
+    }
+}
+
+// A page table entry that maps a user frame. Kept per frame so the
+// allocator can find and rewrite every mapping when it evicts the frame.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Mapping {
+    pub pid: u32,
+    pub va: u64,
+    pub table: u32,
+    pub index: usize,
+}
+
+// The allocator's view of resident frames handed to a replacement policy.
+pub struct Frames<'a> {
+    frames: &'a mut Vec<Page>,
+    rmap: &'a HashMap<u32, Vec<Mapping>>,
+    pinned: &'a HashSet<u32>,
+    // pages of mapped files, which can go back to their file
+    cached: &'a HashMap<u32, (u32, u64)>,
+    // whether there is a swap area for anonymous pages to go to
+    swap: bool,
+    tlb: &'a mut Tlb,
+    // the index of the reference being made
+    now: usize,
+    entry_size: usize,
+    // when set, only frames this process alone maps may be evicted
+    owner: Option<u32>,
+}
+
+impl<'a> Frames<'a> {
+    pub fn evictable(&self, ppn: u32) -> bool {
+        if self.pinned.contains(&ppn) {
+            return false;
+        }
+        // an anonymous page would lose its contents with nowhere to go
+        if !self.swap && !self.cached.contains_key(&ppn) {
+            return false;
+        }
+        match self.rmap.get(&ppn) {
+            Some(maps) => maps.iter().all(|m| {
+                !self.pte(m).get_flag(Flag::Protected) && self.owner.is_none_or(|pid| m.pid == pid)
+            }),
+            None => false
+        }
+    }
+
+    pub fn now(&self) -> usize {
+        self.now
+    }
+
+    pub fn accessed(&self, ppn: u32) -> bool {
+        match self.rmap.get(&ppn) {
+            Some(maps) => maps.iter().any(|m| self.pte(m).get_flag(Flag::Accessed)),
+            None => false
+        }
+    }
+
+    pub fn dirty(&self, ppn: u32) -> bool {
+        match self.rmap.get(&ppn) {
+            Some(maps) => maps.iter().any(|m| self.pte(m).get_flag(Flag::Dirty)),
+            None => false
+        }
+    }
+
+    // Every entry that maps the frame.
+    pub fn mappings(&self, ppn: u32) -> &[Mapping] {
+        match self.rmap.get(&ppn) {
+            Some(maps) => maps,
+            None => &[]
+        }
+    }
+
+    pub fn clear_accessed(&mut self, ppn: u32) {
+        if let Some(maps) = self.rmap.get(&ppn) {
+            for m in maps {
+                let mut pte = self.pte(m);
+                pte.clear_flag(Flag::Accessed);
+                write_entry(&mut self.frames[m.table as usize], m.index, self.entry_size, pte);
+                // a cached copy of the entry would set the flag again
+                self.tlb.invalidate(m.pid, m.va);
+            }
+        }
+    }
+
+    fn pte(&self, m: &Mapping) -> PTE {
+        read_entry(&self.frames[m.table as usize], m.index, self.entry_size)
+    }
+}
+
+// Entries are stored little endian in 4 or 8 bytes depending on the
+// paging mode. A 4-byte entry has no room above its page number, so the
+// kernel's own flags go in bits the simulator has no other use for:
+// write-through, cache-disable and the top bit of the page number,
+// which is why 32-bit paging holds half as many frames.
+const NARROW_FLAGS: [(Flag, u32); 3] = [(Flag::CopyOnWrite, 3), (Flag::ProtNone, 4), (Flag::NoExecute, 31)];
+const NARROW_MASK: u32 = 1 << 3 | 1 << 4 | 1 << 31;
+
+fn read_entry(table: &Page, index: usize, entry_size: usize) -> PTE {
+    let raw = match entry_size {
+        4 => table.read::<u32>(index * 4),
+        _ => table.read::<u64>(index * 8),
+    };
+    let mut val = 0u64;
+    for (i, &byte) in raw.iter().enumerate() {
+        val |= (byte as u64) << (i * 8);
+    }
+    if entry_size != 4 {
+        return PTE::from(val);
+    }
+    let mut pte = PTE::from(val & !(NARROW_MASK as u64));
+    for (flag, bit) in NARROW_FLAGS {
+        if (val >> bit) & 1 == 1 {
+            pte.set_flag(flag);
+        }
+    }
+    pte
+}
+
+fn write_entry(table: &mut Page, index: usize, entry_size: usize, pte: PTE) {
+    match entry_size {
+        4 => {
+            let mut val = pte.get() as u32 & !NARROW_MASK;
+            for (flag, bit) in NARROW_FLAGS {
+                if pte.get_flag(flag) {
+                    val |= 1 << bit;
+                }
+            }
+            table.write::<u32>(index * 4, &val.to_le_bytes());
+        },
+        _ => table.write::<u64>(index * 8, &pte.get().to_le_bytes()),
     }
 }
 
-struct Memory {
-    free_list: Vec<Page>,
-    used_list: Vec<Page>,
-    zero_page: &'static Page,
+// Carries what a mapping allows over to the entry that replaces it when
+// its frame goes away. A write then has to copy whatever the new entry
+// refers to, unless `writable` keeps the old write permission as is.
+fn keep_permissions(old: PTE, new: &mut PTE, writable: bool) {
+    for flag in [Flag::Protected, Flag::ProtNone, Flag::NoExecute, Flag::CopyOnWrite] {
+        if old.get_flag(flag) {
+            new.set_flag(flag);
+        }
+    }
+    if old.get_flag(Flag::Writable) {
+        new.set_flag(if writable { Flag::Writable } else { Flag::CopyOnWrite });
+    }
+}
+
+pub struct Memory {
+    frames: Vec<Page>,
+    free_list: Vec<u32>,
+    rmap: HashMap<u32, Vec<Mapping>>,
+    // how many of the mappings in `rmap` each process has
+    resident: HashMap<u32, usize>,
+    pinned: HashSet<u32>,
+    policy: Box<dyn ReplacementPolicy>,
+    swap: Option<SwapDevice>,
+    // frames whose contents still match the swap slot they were read
+    // from by slot, and the other way around
+    swap_cache: HashMap<u32, u32>,
+    swap_slots: HashMap<u32, u32>,
+    log: EventLog,
+    tlb: Tlb,
+    // references made so far by every process
+    references: usize,
+    entry_size: usize,
+    walks: Walks,
+    walks_per_pid: HashMap<u32, Walks>,
+    // first frames of the runs backing large pages, which page
+    // replacement never sees
+    large: HashSet<u32>,
+    large_frames: usize,
+    files: HashMap<u32, MappedFile>,
+    next_file: u32,
+    // pages of mapped files by file and offset, and the other way around
+    page_cache: HashMap<(u32, u64), u32>,
+    cached: HashMap<u32, (u32, u64)>,
 }
 
 impl Memory {
-    fn new() -> Self {
-        let mut v: Vec<Page> = Vec::new();
-        for ppn in 1..32 {
-            v.push(Page::new(32 - ppn));
+    pub fn new(nframes: u32, geo: &Geometry, policy: Box<dyn ReplacementPolicy>, tlb: Tlb, log: EventLog) -> Self {
+        let page_size = geo.page_size();
+        // frame 0 is reserved for the zero page
+        let mut frames: Vec<Page> = Vec::new();
+        let mut v: Vec<u32> = Vec::new();
+        for ppn in 0..nframes {
+            frames.push(Page::new(ppn, page_size));
+        }
+        for ppn in 1..nframes {
+            v.push(nframes - ppn);
         }
         Self {
+            frames,
             free_list: v,
-            used_list: Vec::new(),
-            zero_page: &ZERO_PAGE,
-        }   
+            rmap: HashMap::new(),
+            resident: HashMap::new(),
+            pinned: HashSet::new(),
+            policy,
+            swap: None,
+            swap_cache: HashMap::new(),
+            swap_slots: HashMap::new(),
+            log,
+            tlb,
+            references: 0,
+            entry_size: geo.entry_size(),
+            walks: Walks::default(),
+            walks_per_pid: HashMap::new(),
+            large: HashSet::new(),
+            large_frames: geo.large_page_frames(),
+            files: HashMap::new(),
+            next_file: 0,
+            page_cache: HashMap::new(),
+            cached: HashMap::new(),
+        }
     }
 
-    fn pop_free(&mut self) -> Option<Page> {
-        self.free_list.pop().map(|mut page| {
-            page.zero();
-            page
-        })
+    fn pop_free(&mut self) -> Option<&mut Page> {
+        if self.free_list.is_empty() {
+            self.evict()?;
+        }
+        let ppn = self.free_list.pop()?;
+        let page = &mut self.frames[ppn as usize];
+        page.zero();
+        Some(page)
     }
 
-    fn push_free(&mut self, page: Page) {
-        self.free_list.push(page);
+    fn push_free(&mut self, ppn: u32) {
+        self.uncache(ppn);
+        self.frames[ppn as usize].ref_count = 0;
+        self.free_list.push(ppn);
     }
 
-    fn push_used(&mut self, page: Page) {
-        self.used_list.push(page);
+    fn uncache(&mut self, ppn: u32) {
+        if let Some(slot) = self.swap_slots.remove(&ppn) {
+            self.swap_cache.remove(&slot);
+            if let Some(swap) = self.swap.as_mut() {
+                swap.free(slot);
+            }
+        }
     }
 
-    fn remove_used(&mut self, page: &Page) -> Option<Page> {
-        let mut idx = self.used_list.len();
-        for i in 0..self.used_list.len() {
-            if self.used_list[i].ppn == page.ppn {
-                idx = i;
-            }
+    fn evict(&mut self) -> Option<u32> {
+        self.evict_from(None)
+    }
+
+    // Evicts the frame the policy picks, from among those `owner` alone
+    // maps if given.
+    fn evict_from(&mut self, owner: Option<u32>) -> Option<u32> {
+        let victim = {
+            let mut frames = Frames {
+                frames: &mut self.frames,
+                rmap: &self.rmap,
+                pinned: &self.pinned,
+                cached: &self.cached,
+                swap: self.swap.is_some(),
+                tlb: &mut self.tlb,
+                now: self.references,
+                entry_size: self.entry_size,
+                owner,
+            };
+            self.policy.victim(&mut frames)?
+        };
+        self.evict_frame(victim)
+    }
+
+    // Writes back or drops the contents of a frame the policy no longer
+    // tracks and points every mapping of it elsewhere.
+    fn evict_frame(&mut self, victim: u32) -> Option<u32> {
+        if self.cached.contains_key(&victim) {
+            return self.evict_file_page(victim);
         }
-        if idx != self.used_list.len() {
-            return Some(self.used_list.remove(idx));
+        let maps = self.rmap.remove(&victim).unwrap_or_default();
+
+        if self.swap.is_none() {
+            // there is no backing store, so the victim stays resident
+            // rather than losing its contents
+            self.rmap.insert(victim, maps);
+            self.policy.insert(victim);
+            return None;
         }
-        return None;
+
+        let slot = match self.swap_out(victim, &maps) {
+            Some(slot) => slot,
+            None => {
+                // the swap area is full, so the victim stays resident
+                self.rmap.insert(victim, maps);
+                self.policy.insert(victim);
+                return None;
+            }
+        };
+
+        // every mapping now refers to the slot instead of the frame
+        self.forget(&maps);
+        let swap = self.swap.as_mut().unwrap();
+        for m in maps {
+            self.tlb.invalidate(m.pid, m.va);
+            let table = &mut self.frames[m.table as usize];
+            let mut pte = read_entry(table, m.index, self.entry_size);
+            pte.set_ppn(slot);
+            pte.clear_flag(Flag::Present);
+            pte.set_flag(Flag::Swapped);
+            write_entry(table, m.index, self.entry_size, pte);
+            swap.dup(slot);
+        }
+        self.policy.remove(victim);
+        self.push_free(victim);
+        self.log.record(EventKind::SwapOut { ppn: victim, slot });
+        Some(victim)
     }
 
-    fn used_peek(&mut self) -> Option<&mut Page> {
-        let idx = self.used_list.len() - 1;
-        self.used_list.get_mut(idx)
+    // A page of a mapped file never goes to swap. It is written back if
+    // dirty and every mapping reads it in from the file again next time.
+    fn evict_file_page(&mut self, victim: u32) -> Option<u32> {
+        if self.writeback(victim).is_err() {
+            self.policy.insert(victim);
+            return None;
+        }
+        let maps = self.rmap.remove(&victim).unwrap_or_default();
+        self.forget(&maps);
+        for m in maps {
+            self.tlb.invalidate(m.pid, m.va);
+            let table = &mut self.frames[m.table as usize];
+            let old = read_entry(table, m.index, self.entry_size);
+            let mut pte = PTE::new(0);
+            pte.set(0, &[Flag::User, Flag::File]);
+            keep_permissions(old, &mut pte, true);
+            write_entry(table, m.index, self.entry_size, pte);
+        }
+        self.uncache_file(victim);
+        self.policy.remove(victim);
+        self.push_free(victim);
+        self.log.record(EventKind::Evict { ppn: victim });
+        Some(victim)
     }
 
-    fn get_zero_ref(&self) -> &Page {
-        self.zero_page
+    // Picks the slot holding the victim's contents, writing them out
+    // unless a clean copy is already in the swap area.
+    fn swap_out(&mut self, victim: u32, maps: &[Mapping]) -> Option<u32> {
+        let dirty = maps.iter().any(|m| {
+            read_entry(&self.frames[m.table as usize], m.index, self.entry_size).get_flag(Flag::Dirty)
+        });
+        if let Some(&slot) = self.swap_slots.get(&victim) {
+            if !dirty {
+                return Some(slot);
+            }
+            self.uncache(victim);
+        }
+
+        let swap = self.swap.as_mut()?;
+        let slot = swap.alloc()?;
+        if swap.write(slot, &self.frames[victim as usize].data).is_err() {
+            // hand the unused slot back
+            swap.dup(slot);
+            swap.free(slot);
+            return None;
+        }
+        Some(slot)
     }
-}
 
-pub fn kinit() {
-    unsafe {
-        MEM = Memory::new()
+    // Brings the swapped-out page behind `mapping` back into a frame and
+    // returns its PPN.
+    pub fn swap_in(&mut self, mapping: Mapping) -> Result<u32, SimError> {
+        let mut pte = self.read_pte(mapping.table, mapping.index);
+        let slot = pte.get_ppn() as u32;
+        if self.swap.is_none() {
+            return Err(SimError::Io("swapped-out page without a swap device".to_string()));
+        }
+
+        // a sharer may have already brought the slot back in
+        let ppn = match self.swap_cache.get(&slot) {
+            Some(&ppn) => {
+                self.frames[ppn as usize].increment_refs();
+                ppn
+            },
+            None => {
+                let ppn = self.kalloc().ok_or(SimError::OutOfMemory)?;
+                let swap = self.swap.as_mut().unwrap();
+                if let Err(err) = swap.read(slot, &mut self.frames[ppn as usize].data) {
+                    self.kfree(ppn);
+                    return Err(err.into());
+                }
+                swap.dup(slot);
+                self.swap_cache.insert(slot, ppn);
+                self.swap_slots.insert(ppn, slot);
+                ppn
+            }
+        };
+        self.swap.as_mut().unwrap().free(slot);
+
+        pte.set_ppn(ppn);
+        pte.clear_flag(Flag::Swapped);
+        pte.clear_flag(Flag::Dirty);
+        pte.set_flag(Flag::Present);
+        pte.set_flag(Flag::Accessed);
+        self.write_pte(mapping.table, mapping.index, pte);
+
+        let maps = self.rmap.entry(ppn).or_default();
+        maps.push(mapping);
+        if maps.len() == 1 {
+            self.policy.insert(ppn);
+        }
+        *self.resident.entry(mapping.pid).or_default() += 1;
+        self.log.record(EventKind::SwapIn { va: mapping.va, ppn, slot });
+        Ok(ppn)
     }
-}
 
-pub fn kalloc() -> Option<&'static mut Page> {
-    unsafe {
-        match MEM.pop_free() {
-            Some(mut page) => {
+    pub fn kalloc(&mut self) -> Option<u32> {
+        let ppn = match self.pop_free() {
+            Some(page) => {
                 page.increment_refs();
-                MEM.push_used(page);
-                MEM.used_peek()
+                page.ppn
             },
-            None => None
+            None => return None
+        };
+        self.log.record(EventKind::Kalloc { ppn });
+        Some(ppn)
+    }
+
+    // Allocates the frames behind a large page. A directory entry can only
+    // map a physical region aligned to its size, so this needs a run of
+    // frames starting on such a boundary. If none is free, the run needing
+    // the fewest evictions is cleared by evicting the user pages in it.
+    // Returns the first frame of the run, which stands for the whole page
+    // from then on.
+    pub fn kalloc_large(&mut self) -> Option<u32> {
+        let n = self.large_frames as u32;
+        let head = self.large_run(n)?;
+        for ppn in head..head + n {
+            if self.rmap.contains_key(&ppn) {
+                self.policy.remove(ppn);
+                self.evict_frame(ppn)?;
+            }
+        }
+        self.free_list.retain(|&ppn| ppn < head || ppn >= head + n);
+        for ppn in head..head + n {
+            self.frames[ppn as usize].zero();
+            self.log.record(EventKind::Kalloc { ppn });
         }
+        self.frames[head as usize].increment_refs();
+        self.large.insert(head);
+        Some(head)
+    }
+
+    // The aligned run of `n` frames holding the fewest user pages, among
+    // those that hold nothing else. Page tables, pinned frames and other
+    // large pages are never moved, so they rule a run out.
+    fn large_run(&mut self, n: u32) -> Option<u32> {
+        let nframes = self.nframes();
+        let mut free = vec![false; self.frames.len()];
+        for &ppn in &self.free_list {
+            free[ppn as usize] = true;
+        }
+        let frames = Frames {
+            frames: &mut self.frames,
+            rmap: &self.rmap,
+            pinned: &self.pinned,
+            cached: &self.cached,
+            swap: self.swap.is_some(),
+            tlb: &mut self.tlb,
+            now: self.references,
+            entry_size: self.entry_size,
+            owner: None,
+        };
+        // the first run holds the zero page
+        (1..nframes / n)
+            .map(|i| i * n)
+            .filter_map(|head| {
+                let mut resident = 0;
+                for ppn in head..head + n {
+                    if free[ppn as usize] {
+                        continue;
+                    }
+                    if !frames.evictable(ppn) {
+                        return None;
+                    }
+                    resident += 1;
+                }
+                Some((resident, head))
+            })
+            .min()
+            .map(|(_, head)| head)
     }
-}
 
-pub fn kfree(page: &Page) {
-    if page.ppn == 0 {
-        return;
+    pub fn kfree(&mut self, ppn: u32) {
+        if ppn == 0 {
+            return;
+        }
+        if self.large.remove(&ppn) {
+            for ppn in ppn..ppn + self.large_frames as u32 {
+                self.push_free(ppn);
+                self.log.record(EventKind::Kfree { ppn });
+            }
+            return;
+        }
+        if let Some(maps) = self.rmap.remove(&ppn) {
+            self.forget(&maps);
+            self.policy.remove(ppn);
+        }
+        self.push_free(ppn);
+        self.log.record(EventKind::Kfree { ppn });
     }
-    unsafe {
-        MEM.push_free(MEM.remove_used(page).unwrap());
+
+    pub fn swapon<P: AsRef<Path>>(&mut self, path: P, slots: u32) -> io::Result<()> {
+        self.check_swapon(slots)?;
+        let page_size = self.page_size();
+        self.swap = Some(SwapDevice::open(path, slots, page_size)?);
+        Ok(())
+    }
+
+    // Attaches a swap area in a temporary file of its own.
+    pub fn swapon_anonymous(&mut self, slots: u32) -> io::Result<()> {
+        self.check_swapon(slots)?;
+        let page_size = self.page_size();
+        self.swap = Some(SwapDevice::anonymous(slots, page_size)?);
+        Ok(())
+    }
+
+    fn check_swapon(&self, slots: u32) -> io::Result<()> {
+        if self.swap.is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "swap device already attached"));
+        }
+        // slots go in place of the page number, which is 19 bits in a 4-byte entry
+        if self.entry_size == 4 && slots > 1 << 19 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many swap slots for 32-bit paging"));
+        }
+        Ok(())
+    }
+
+    // Opens a file for mapping and returns its id. A file that is mapped
+    // already gets another reference, so both mappings share its pages.
+    pub fn open_file<P: AsRef<Path>>(&mut self, path: P, writable: bool) -> io::Result<u32> {
+        let path = fs::canonicalize(path)?;
+        if let Some((&id, file)) = self.files.iter_mut().find(|(_, f)| !f.is_segment() && f.path() == path) {
+            if writable {
+                file.make_writable()?;
+            }
+            file.dup();
+            return Ok(id);
+        }
+        let id = self.next_file;
+        self.next_file += 1;
+        self.files.insert(id, MappedFile::open(path, writable)?);
+        Ok(id)
+    }
+
+    // Opens the shared memory segment called `name`, creating a zeroed
+    // one of `len` bytes if there is none, and returns its id. Segments
+    // share the ids of mapped files and, like them, go away with their
+    // last reference.
+    pub fn open_segment(&mut self, name: &str, len: u64) -> Result<u32, SimError> {
+        if let Some((&id, seg)) = self.files.iter_mut().find(|(_, f)| f.is_segment() && f.path().as_os_str() == name) {
+            if seg.size()? < len {
+                return Err(SimError::InvalidArgument("segment is smaller than requested"));
+            }
+            seg.dup();
+            return Ok(id);
+        }
+        let id = self.next_file;
+        self.next_file += 1;
+        self.files.insert(id, MappedFile::segment(name, len));
+        Ok(id)
+    }
+
+    // The size of the segment with the given id.
+    pub fn segment_size(&self, id: u32) -> Option<u64> {
+        match self.files.get(&id) {
+            Some(seg) if seg.is_segment() => seg.size().ok(),
+            _ => None
+        }
+    }
+
+    pub fn dup_file(&mut self, id: u32) {
+        if let Some(file) = self.files.get_mut(&id) {
+            file.dup();
+        }
+    }
+
+    // Drops a reference to a mapped file. Once the last mapping is gone
+    // its pages leave the page cache and the file is closed. Dirty pages
+    // were written back when they were unmapped.
+    pub fn close_file(&mut self, id: u32) {
+        let left = match self.files.get_mut(&id) {
+            Some(file) => file.release(),
+            None => return
+        };
+        if left > 0 {
+            return;
+        }
+        self.files.remove(&id);
+        let pages: Vec<u32> = self.page_cache.iter()
+            .filter(|&(&(file, _), _)| file == id)
+            .map(|(_, &ppn)| ppn)
+            .collect();
+        for ppn in pages {
+            self.uncache_file(ppn);
+            if self.frames[ppn as usize].ref_count() > 1 {
+                self.frames[ppn as usize].decrement_refs();
+            } else {
+                self.kfree(ppn);
+            }
+        }
+    }
+
+    // Lets a shared mapping of a file opened read-only become writable.
+    pub fn file_writable(&mut self, id: u32) -> io::Result<()> {
+        match self.files.get_mut(&id) {
+            Some(file) => file.make_writable(),
+            None => Ok(())
+        }
+    }
+
+    pub fn mapped_file(&self, id: u32) -> Option<&MappedFile> {
+        self.files.get(&id)
+    }
+
+    // The frame holding the page of a mapped file at `offset`, read in on
+    // a page cache miss. The page cache keeps a reference of its own and
+    // the caller gets another one for its mapping.
+    pub fn file_page(&mut self, id: u32, offset: u64) -> Result<u32, SimError> {
+        if let Some(&ppn) = self.page_cache.get(&(id, offset)) {
+            self.frames[ppn as usize].increment_refs();
+            return Ok(ppn);
+        }
+        if !self.files.contains_key(&id) {
+            return Err(SimError::InvalidArgument("page of a file that is not mapped"));
+        }
+        let ppn = self.kalloc().ok_or(SimError::OutOfMemory)?;
+        let file = self.files.get_mut(&id).unwrap();
+        if let Err(err) = file.read(offset, &mut self.frames[ppn as usize].data) {
+            self.kfree(ppn);
+            return Err(err.into());
+        }
+        // the pages of a segment have nowhere else to go, so they stay
+        // resident as long as it exists
+        if file.is_segment() {
+            self.pinned.insert(ppn);
+        }
+        self.page_cache.insert((id, offset), ppn);
+        self.cached.insert(ppn, (id, offset));
+        self.frames[ppn as usize].increment_refs();
+        Ok(ppn)
+    }
+
+    fn uncache_file(&mut self, ppn: u32) {
+        if let Some(key) = self.cached.remove(&ppn) {
+            self.page_cache.remove(&key);
+            // a segment page was pinned for as long as it was cached
+            self.pinned.remove(&ppn);
+        }
+    }
+
+    // Writes a page of a mapped file back if any mapping dirtied it and
+    // marks every mapping clean again. Other frames are left alone.
+    pub fn writeback(&mut self, ppn: u32) -> io::Result<()> {
+        let (id, offset) = match self.cached.get(&ppn) {
+            Some(&key) if self.segment_size(key.0).is_none() => key,
+            _ => return Ok(())
+        };
+        let maps = self.rmap.get(&ppn).cloned().unwrap_or_default();
+        if !maps.iter().any(|m| self.read_pte(m.table, m.index).get_flag(Flag::Dirty)) {
+            return Ok(());
+        }
+        let file = self.files.get_mut(&id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "page of a file that is not mapped"))?;
+        file.write(offset, &self.frames[ppn as usize].data)?;
+        for m in maps {
+            self.tlb.invalidate(m.pid, m.va);
+            let mut pte = self.read_pte(m.table, m.index);
+            pte.clear_flag(Flag::Dirty);
+            self.write_pte(m.table, m.index, pte);
+        }
+        self.log.record(EventKind::Writeback { ppn });
+        Ok(())
+    }
+
+    pub fn cached_frames(&self) -> usize {
+        self.page_cache.len()
+    }
+
+    // Frames holding pages of shared memory segments, which are also
+    // counted as cached.
+    pub fn segment_frames(&self) -> usize {
+        self.page_cache.keys()
+            .filter(|&&(id, _)| self.segment_size(id).is_some())
+            .count()
+    }
+
+    // Whether more than one mapping refers to the frame. The page cache's
+    // own reference to a file page does not count.
+    pub fn shared(&self, ppn: u32) -> bool {
+        let mut refs = self.frames[ppn as usize].ref_count();
+        if self.cached.contains_key(&ppn) {
+            refs -= 1;
+        }
+        refs > 1
+    }
+
+    pub fn swap_device(&self) -> Option<&SwapDevice> {
+        self.swap.as_ref()
+    }
+
+    pub fn swap_dup(&mut self, slot: u32) {
+        if let Some(swap) = self.swap.as_mut() {
+            swap.dup(slot);
+        }
+    }
+
+    pub fn swap_free(&mut self, slot: u32) {
+        if let Some(swap) = self.swap.as_mut() {
+            swap.free(slot);
+        }
+    }
+
+    // The frame is about to diverge from its swap slot.
+    pub fn dirty(&mut self, ppn: u32) {
+        self.uncache(ppn);
+    }
+
+    pub fn frame(&self, ppn: u32) -> &Page {
+        &self.frames[ppn as usize]
+    }
+
+    pub fn frame_mut(&mut self, ppn: u32) -> &mut Page {
+        &mut self.frames[ppn as usize]
+    }
+
+    pub fn zero_page(&self) -> &Page {
+        self.frame(0)
+    }
+
+    // Copies a frame, or every frame of a large page when `src` heads one.
+    pub fn copy_frame(&mut self, dst: u32, src: u32) {
+        let n = if self.large.contains(&src) { self.large_frames as u32 } else { 1 };
+        for i in 0..n {
+            let data = self.frames[(src + i) as usize].data.clone();
+            self.frames[(dst + i) as usize].data = data;
+        }
+    }
+
+    pub fn nframes(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_list.len()
+    }
+
+    // User frames currently mapped by at least one process.
+    pub fn resident_frames(&self) -> impl Iterator<Item = u32> + '_ {
+        self.rmap.keys().copied()
+    }
+
+    // Pages of `pid` backed by a frame page replacement can see.
+    pub fn resident_pages(&self, pid: u32) -> usize {
+        self.resident.get(&pid).copied().unwrap_or(0)
     }
-}
 
-pub fn zero_page() -> &'static Page {
-    let zero: &Page;
-    zero = unsafe { MEM.get_zero_ref() };
-    zero
-}
\ No newline at end of file
+    // The pages of `pid` referenced since the last call, found by their
+    // accessed bits, which are cleared for the next one.
+    pub fn sample_accessed(&mut self, pid: u32) -> Vec<u64> {
+        let mut referenced = Vec::new();
+        for m in self.rmap.values().flatten().filter(|m| m.pid == pid) {
+            let table = &mut self.frames[m.table as usize];
+            let mut pte = read_entry(table, m.index, self.entry_size);
+            if pte.get_flag(Flag::Accessed) {
+                pte.clear_flag(Flag::Accessed);
+                write_entry(table, m.index, self.entry_size, pte);
+                self.tlb.invalidate(m.pid, m.va);
+                referenced.push(m.va);
+            }
+        }
+        referenced
+    }
+
+    // Evicts pages only `pid` maps until it has at most `limit` resident,
+    // or nothing more of its own can go. Returns how many went.
+    pub fn shrink(&mut self, pid: u32, limit: usize) -> usize {
+        let mut evicted = 0;
+        while self.resident_pages(pid) > limit && self.evict_from(Some(pid)).is_some() {
+            evicted += 1;
+        }
+        evicted
+    }
+
+    // The first frame of every large page in use.
+    pub fn large_pages(&self) -> impl Iterator<Item = u32> + '_ {
+        self.large.iter().copied()
+    }
+
+    pub fn large_page_frames(&self) -> usize {
+        self.large_frames
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.frames[0].size()
+    }
+
+    pub fn policy(&self) -> &dyn ReplacementPolicy {
+        self.policy.as_ref()
+    }
+
+    // Records that a user frame is mapped by the given entry so it
+    // becomes a candidate for replacement. Large pages stay resident.
+    pub fn track(&mut self, ppn: u32, mapping: Mapping) {
+        if ppn == 0 || self.large.contains(&ppn) {
+            return;
+        }
+        let maps = self.rmap.entry(ppn).or_default();
+        maps.push(mapping);
+        if maps.len() == 1 {
+            self.policy.insert(ppn);
+        }
+        *self.resident.entry(mapping.pid).or_default() += 1;
+    }
+
+    pub fn untrack(&mut self, ppn: u32, table: u32, index: usize) {
+        let maps = match self.rmap.get_mut(&ppn) {
+            Some(maps) => maps,
+            None => return
+        };
+        let gone: Vec<Mapping> = maps.iter().filter(|m| m.table == table && m.index == index).copied().collect();
+        maps.retain(|m| m.table != table || m.index != index);
+        if maps.is_empty() {
+            self.rmap.remove(&ppn);
+            self.policy.remove(ppn);
+        }
+        self.forget(&gone);
+    }
+
+    // Takes mappings that no longer refer to their frame off the counts
+    // of resident pages.
+    fn forget(&mut self, maps: &[Mapping]) {
+        for m in maps {
+            if let Some(count) = self.resident.get_mut(&m.pid) {
+                *count -= 1;
+                if *count == 0 {
+                    self.resident.remove(&m.pid);
+                }
+            }
+        }
+    }
+
+    // Counts a reference once it is made, so a policy replaying a
+    // recorded reference string knows where it is.
+    pub fn referenced(&mut self) {
+        self.references += 1;
+    }
+
+    pub fn touch(&mut self, ppn: u32) {
+        if self.rmap.contains_key(&ppn) {
+            self.policy.touch(ppn);
+        }
+    }
+
+    // Pinned frames are never chosen as victims, e.g. while a fault
+    // handler is still copying out of them.
+    pub fn pin(&mut self, ppn: u32) {
+        self.pinned.insert(ppn);
+    }
+
+    pub fn unpin(&mut self, ppn: u32) {
+        self.pinned.remove(&ppn);
+    }
+
+    pub fn tlb(&self) -> &Tlb {
+        &self.tlb
+    }
+
+    pub fn tlb_mut(&mut self) -> &mut Tlb {
+        &mut self.tlb
+    }
+
+    pub fn log(&self) -> &EventLog {
+        &self.log
+    }
+
+    pub fn record(&mut self, kind: EventKind) {
+        self.log.record(kind);
+    }
+
+    // Events that follow are attributed to `pid`.
+    pub fn set_pid(&mut self, pid: u32) {
+        self.log.set_pid(pid);
+    }
+
+    pub fn read_pte(&self, table: u32, index: usize) -> PTE {
+        read_entry(self.frame(table), index, self.entry_size)
+    }
+
+    pub fn write_pte(&mut self, table: u32, index: usize, pte: PTE) {
+        let entry_size = self.entry_size;
+        write_entry(self.frame_mut(table), index, entry_size, pte);
+    }
+
+    // Accounts for a page walk by `pid` that read `reads` entries.
+    pub fn count_walk(&mut self, pid: u32, reads: usize) {
+        for walks in [&mut self.walks, self.walks_per_pid.entry(pid).or_default()] {
+            walks.count += 1;
+            walks.reads += reads as u64;
+        }
+    }
+
+    pub fn walks(&self) -> Walks {
+        self.walks
+    }
+
+    pub fn walks_for(&self, pid: u32) -> Walks {
+        self.walks_per_pid.get(&pid).copied().unwrap_or_default()
+    }
+}
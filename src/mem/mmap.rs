@@ -0,0 +1,166 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// What a mapping lets the process do with its pages.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Prot {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl Prot {
+    pub const NONE: Prot = Prot { read: false, write: false, exec: false };
+    pub const READ: Prot = Prot { read: true, write: false, exec: false };
+    pub const READ_WRITE: Prot = Prot { read: true, write: true, exec: false };
+    pub const READ_EXEC: Prot = Prot { read: true, write: false, exec: true };
+    pub const READ_WRITE_EXEC: Prot = Prot { read: true, write: true, exec: true };
+}
+
+// Permissions written the way ls shows them, e.g. "r-x" or "rw-".
+impl FromStr for Prot {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            [r, w, x] => {
+                let bit = |c: u8, on: u8| match c {
+                    b'-' => Ok(false),
+                    c if c == on => Ok(true),
+                    _ => Err(()),
+                };
+                Ok(Self { read: bit(*r, b'r')?, write: bit(*w, b'w')?, exec: bit(*x, b'x')? })
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+// Whether writes to a mapped file reach the file and every other process
+// mapping it, or stay private to the process through copy-on-write.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Sharing {
+    Shared,
+    Private,
+}
+
+enum Backing {
+    File(File),
+    // a shared memory segment of this many bytes, whose pages only ever
+    // live in memory
+    Memory(u64),
+}
+
+// A local file mapped into one or more address spaces, or a shared memory
+// segment. Every mapping holds a reference and the file is closed when
+// the last one goes away. A segment also has one for every open handle.
+pub struct MappedFile {
+    path: PathBuf,
+    backing: Backing,
+    writable: bool,
+    refs: usize,
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P, writable: bool) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            backing: Backing::File(file),
+            writable,
+            refs: 1,
+        })
+    }
+
+    // A zeroed segment of `len` bytes known by `name`.
+    pub fn segment(name: &str, len: u64) -> Self {
+        Self {
+            path: PathBuf::from(name),
+            backing: Backing::Memory(len),
+            writable: true,
+            refs: 1,
+        }
+    }
+
+    // The path of a file, or the name of a segment.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_segment(&self) -> bool {
+        matches!(self.backing, Backing::Memory(_))
+    }
+
+    pub fn size(&self) -> io::Result<u64> {
+        match &self.backing {
+            Backing::File(file) => Ok(file.metadata()?.len()),
+            Backing::Memory(len) => Ok(*len),
+        }
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn refs(&self) -> usize {
+        self.refs
+    }
+
+    // Reopens the file for writing once a shared writable mapping needs it.
+    pub fn make_writable(&mut self) -> io::Result<()> {
+        if !self.writable {
+            let file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+            self.backing = Backing::File(file);
+            self.writable = true;
+        }
+        Ok(())
+    }
+
+    pub fn dup(&mut self) {
+        self.refs += 1;
+    }
+
+    // Drops a reference and returns how many are left.
+    pub fn release(&mut self) -> usize {
+        self.refs -= 1;
+        self.refs
+    }
+
+    // Reads the page at `offset`. Whatever lies past the end of the
+    // file reads as zeros.
+    pub fn read(&mut self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        data.iter_mut().for_each(|byte| *byte = 0);
+        let file = match &mut self.backing {
+            Backing::File(file) => file,
+            Backing::Memory(_) => return Ok(()),
+        };
+        let len = file.metadata()?.len();
+        if offset >= len {
+            return Ok(());
+        }
+        let n = data.len().min((len - offset) as usize);
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data[..n])
+    }
+
+    // Writes the page at `offset` back. A mapping never grows the file,
+    // so the part of the page past its end is dropped.
+    pub fn write(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let file = match &mut self.backing {
+            Backing::File(file) => file,
+            Backing::Memory(_) => return Ok(()),
+        };
+        let len = file.metadata()?.len();
+        if offset >= len {
+            return Ok(());
+        }
+        let n = data.len().min((len - offset) as usize);
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&data[..n])
+    }
+}
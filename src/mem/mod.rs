@@ -1,2 +1,6 @@
 pub mod ptable;
 pub mod alloc;
+pub mod replace;
+pub mod swap;
+pub mod mmap;
+pub mod tlb;
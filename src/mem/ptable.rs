@@ -1,11 +1,20 @@
 #![allow(dead_code, unused)]
 
+use std::str::FromStr;
+
 pub type PTE = PageTableEntry;
 
-pub const KERNBASE: u32 = 0x80000000;
+pub const KERNBASE: u64 = 0x80000000;
 pub const PDXSHIFT: usize = 22;
 pub const PTXSHIFT: usize = 12;
 pub const PAGESIZE: usize = 4096;
+pub const NPTENTRIES: usize = 1024;
+
+// entries keep their flags in the low 12 bits whatever the page size
+const PPNSHIFT: usize = 12;
+// entries hold page numbers in bits 12 to 51, as on x86-64, which leaves
+// bits 52 and up for flags only the kernel looks at
+const PPNMASK: u64 = 0x000F_FFFF_FFFF_F000;
 
 #[derive(Copy, Clone)]
 pub enum Flag {
@@ -18,14 +27,25 @@ pub enum Flag {
     Dirty,
     Protected,
     Zero,
+    Swapped,
+    // a directory entry mapping a large page instead of a page table
+    PageSize,
+    // a page of a mapped file that has not been read in yet
+    File,
+    // writes are allowed, but the frame is shared and must be copied first
+    CopyOnWrite,
+    // the page may not be accessed at all
+    ProtNone,
+    // instructions may not be fetched from the page, bit 63 as on x86-64
+    NoExecute,
 }
 
 #[derive(Clone, Copy)]
-pub struct PageTableEntry(u32);
+pub struct PageTableEntry(u64);
 
 impl PageTableEntry {
     pub(crate) fn new(ppn: u32) -> Self {
-        Self(ppn << PTXSHIFT & !0xFFF)
+        Self((ppn as u64) << PPNSHIFT & PPNMASK)
     }
 
     pub fn get_flag(&self, flag: Flag) -> bool {
@@ -39,6 +59,12 @@ impl PageTableEntry {
             Flag::Dirty => (self.0 >> 6) & 1 == 1,
             Flag::Protected => (self.0 >> 7) & 1 == 1,
             Flag::Zero => (self.0 >> 8) & 1 == 1,
+            Flag::Swapped => (self.0 >> 9) & 1 == 1,
+            Flag::PageSize => (self.0 >> 10) & 1 == 1,
+            Flag::File => (self.0 >> 11) & 1 == 1,
+            Flag::CopyOnWrite => (self.0 >> 52) & 1 == 1,
+            Flag::ProtNone => (self.0 >> 53) & 1 == 1,
+            Flag::NoExecute => (self.0 >> 63) & 1 == 1,
         }
     }
 
@@ -53,59 +79,215 @@ impl PageTableEntry {
             Flag::Dirty => self.0 |= 1 << 6,
             Flag::Protected => self.0 |= 1 << 7,
             Flag::Zero => self.0 |= 1 << 8,
+            Flag::Swapped => self.0 |= 1 << 9,
+            Flag::PageSize => self.0 |= 1 << 10,
+            Flag::File => self.0 |= 1 << 11,
+            Flag::CopyOnWrite => self.0 |= 1 << 52,
+            Flag::ProtNone => self.0 |= 1 << 53,
+            Flag::NoExecute => self.0 |= 1 << 63,
         }
     }
 
     pub fn clear_flag(&mut self, flag: Flag) {
         match flag {
-            Flag::Present => self.0 &= !(1u32),
-            Flag::Writable => self.0 &= !(1u32 << 1),
-            Flag::User => self.0 &= !(1u32 << 2),
-            Flag::WriteThrough => self.0 &= !(1u32 << 3),
-            Flag::CacheDisable => self.0 &= !(1u32 << 4),
-            Flag::Accessed => self.0 &= !(1u32 << 5),
-            Flag::Dirty => self.0 &= !(1u32 << 6),
-            Flag::Protected => self.0 &= !(1u32 << 7),
-            Flag::Zero => self.0 &= !(1u32 << 8),
+            Flag::Present => self.0 &= !(1u64),
+            Flag::Writable => self.0 &= !(1u64 << 1),
+            Flag::User => self.0 &= !(1u64 << 2),
+            Flag::WriteThrough => self.0 &= !(1u64 << 3),
+            Flag::CacheDisable => self.0 &= !(1u64 << 4),
+            Flag::Accessed => self.0 &= !(1u64 << 5),
+            Flag::Dirty => self.0 &= !(1u64 << 6),
+            Flag::Protected => self.0 &= !(1u64 << 7),
+            Flag::Zero => self.0 &= !(1u64 << 8),
+            Flag::Swapped => self.0 &= !(1u64 << 9),
+            Flag::PageSize => self.0 &= !(1u64 << 10),
+            Flag::File => self.0 &= !(1u64 << 11),
+            Flag::CopyOnWrite => self.0 &= !(1u64 << 52),
+            Flag::ProtNone => self.0 &= !(1u64 << 53),
+            Flag::NoExecute => self.0 &= !(1u64 << 63),
         }
     }
 
     pub fn get_ppn(&self) -> usize {
-        (self.0 >> PTXSHIFT) as usize
+        ((self.0 & PPNMASK) >> PPNSHIFT) as usize
     }
 
-    pub fn set(&mut self, pa: u32, flags: &[Flag]) {
-        self.0 = pa & !0xFFFu32;
+    pub fn set(&mut self, pa: u64, flags: &[Flag]) {
+        self.0 = pa & PPNMASK;
         for &flag in flags {
             self.set_flag(flag);
         }
     }
 
-    pub fn get_address(&self) -> u32 {
-        self.0 & !0xFFFu32
+    pub fn get_address(&self) -> u64 {
+        self.0 & PPNMASK
+    }
+
+    // Replaces the page number while keeping the flags. A swapped-out
+    // entry is not present and keeps its swap slot in place of the PPN.
+    pub fn set_ppn(&mut self, ppn: u32) {
+        self.0 = PTE::new(ppn).get_address() | (self.0 & !PPNMASK);
     }
 
-    pub fn get(&self) -> u32 {
+    pub fn get(&self) -> u64 {
         self.0
     }
 }
 
-impl From<u32> for PageTableEntry {
-    fn from(num: u32) -> Self {
+impl From<u64> for PageTableEntry {
+    fn from(num: u64) -> Self {
         Self(num)
     }
 }
 
+// How wide virtual addresses are. Every table fills one page, so the
+// width, the page size and the entry size decide how many levels a walk
+// goes through; with 4 KiB pages these are the i386 and x86-64 layouts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PagingMode {
+    // 32-bit addresses and 4-byte entries, two levels
+    Bits32,
+    // 48-bit canonical addresses and 8-byte entries, four levels
+    Bits48,
+    // 57-bit canonical addresses and 8-byte entries, five levels
+    Bits57,
+}
+
+impl PagingMode {
+    pub fn va_bits(&self) -> usize {
+        match *self {
+            Self::Bits32 => 32,
+            Self::Bits48 => 48,
+            Self::Bits57 => 57,
+        }
+    }
+
+    pub fn entry_size(&self) -> usize {
+        match *self {
+            Self::Bits32 => 4,
+            Self::Bits48 | Self::Bits57 => 8,
+        }
+    }
+}
+
+impl FromStr for PagingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "32" | "i386" | "two-level" => Ok(Self::Bits32),
+            "48" | "x86-64" | "x86_64" | "four-level" => Ok(Self::Bits48),
+            "57" | "la57" | "five-level" => Ok(Self::Bits57),
+            _ => Err(()),
+        }
+    }
+}
+
+// The shape of the translation, derived from the page size and paging
+// mode. Level 0 is the page directory and the last level holds the
+// entries that map pages. The virtual address space starts at `base`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Geometry {
+    page_size: usize,
+    ptxshift: usize,
+    index_bits: usize,
+    levels: usize,
+    mode: PagingMode,
+    base: u64,
+}
+
+impl Geometry {
+    pub fn new(page_size: usize, base: u64, mode: PagingMode) -> Self {
+        let ptxshift = page_size.trailing_zeros() as usize;
+        let index_bits = (page_size / mode.entry_size()).trailing_zeros() as usize;
+        let levels = (mode.va_bits() - ptxshift).div_ceil(index_bits);
+        Self {
+            page_size,
+            ptxshift,
+            index_bits,
+            levels,
+            mode,
+            base,
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn entries(&self) -> usize {
+        1 << self.index_bits
+    }
+
+    pub fn entry_size(&self) -> usize {
+        self.mode.entry_size()
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn mode(&self) -> PagingMode {
+        self.mode
+    }
+
+    pub fn ptxshift(&self) -> usize {
+        self.ptxshift
+    }
+
+    // The lowest address bit indexed at `level`.
+    pub fn shift(&self, level: usize) -> usize {
+        self.ptxshift + (self.levels - 1 - level) * self.index_bits
+    }
+
+    // The span of one entry in the tables just above the leaves, which is
+    // what a large page maps: 4 MiB for i386 and 2 MiB for x86-64.
+    pub fn large_page_size(&self) -> u64 {
+        1 << self.shift(self.levels - 2)
+    }
+
+    // How many frames back one large page.
+    pub fn large_page_frames(&self) -> usize {
+        self.entries()
+    }
+
+    pub fn index(&self, va: u64, level: usize) -> usize {
+        (va >> self.shift(level)) as usize & (self.entries() - 1)
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    // 32-bit addresses must fit in 32 bits, wider ones must have every
+    // bit above the top translated bit equal to it.
+    pub fn canonical(&self, va: u64) -> bool {
+        match self.mode {
+            PagingMode::Bits32 => va >> 32 == 0,
+            mode => {
+                let top = (va as i64) >> (mode.va_bits() - 1);
+                top == 0 || top == -1
+            }
+        }
+    }
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self::new(PAGESIZE, KERNBASE, PagingMode::Bits32)
+    }
+}
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Address {
-    Virtual(u32, usize),
-    Physical(u32, usize),
+    Virtual(u64, usize),
+    Physical(u64, usize),
 }
 
 #[derive(Copy, Clone)]
 pub struct Virtual(Address);
 impl Virtual {
-    pub fn new(vaddr: u32, ptr: usize) -> Self {
+    pub fn new(vaddr: u64, ptr: usize) -> Self {
         Self(Address::Virtual(vaddr, ptr))
     }
 
@@ -113,9 +295,9 @@ impl Virtual {
         self.0
     }
 
-    pub fn as_phys(&self) -> Physical {
+    pub fn as_phys(&self, geo: &Geometry) -> Physical {
         Physical::new(
-            self.0.translate().get_address(),
+            self.0.translate(geo).get_address(geo),
             self.0.get_ptr() as usize
         )
     }
@@ -124,7 +306,7 @@ impl Virtual {
 #[derive(Copy, Clone)]
 pub struct Physical(Address);
 impl Physical {
-    pub fn new(paddr: u32, ptr: usize) -> Self {
+    pub fn new(paddr: u64, ptr: usize) -> Self {
         Self(Address::Physical(paddr, ptr))
     }
 
@@ -132,48 +314,43 @@ impl Physical {
         self.0
     }
 
-    pub fn from(ppn: u32, offset: u32, ptr: usize) -> Self {
+    pub fn from(ppn: u64, offset: u64, ptr: usize) -> Self {
         let paddr = ppn | offset;
         Self(Address::Physical(paddr, ptr))
     }
 }
 
 impl Address {
-    pub fn translate(&self) -> Self {
+    pub fn translate(&self, geo: &Geometry) -> Self {
         match *self {
-            Self::Virtual(vaddr, ptr) => Self::Physical(vaddr - KERNBASE, ptr),
-            Self::Physical(paddr, ptr) => Self::Virtual(paddr + KERNBASE, ptr)
+            Self::Virtual(vaddr, ptr) => Self::Physical(vaddr.wrapping_sub(geo.base), ptr),
+            Self::Physical(paddr, ptr) => Self::Virtual(paddr.wrapping_add(geo.base), ptr)
         }
     }
 
-    pub fn get_address(&self) -> u32 {
+    pub fn get_address(&self, geo: &Geometry) -> u64 {
+        let mask = !(geo.page_size as u64 - 1);
         match *self {
-            Self::Virtual(vaddr, _) => vaddr & !0xFFF,
-            Self::Physical(paddr, _) => paddr & !0xFFF
+            Self::Virtual(vaddr, _) => vaddr & mask,
+            Self::Physical(paddr, _) => paddr & mask
         }
     }
 
-    pub fn get_dir_index(&self) -> usize {
+    // The index into the table at `level` of a walk for this address.
+    pub fn get_index(&self, geo: &Geometry, level: usize) -> usize {
         match *self {
-            Self::Virtual(vaddr, _) => ((vaddr >> PDXSHIFT) & 0x3FF) as usize,
+            Self::Virtual(vaddr, _) => geo.index(vaddr, level),
             Self::Physical(_, _) => 0,
         }
     }
 
-    pub fn get(&self) -> u32 {
+    pub fn get(&self) -> u64 {
         match *self {
             Self::Virtual(vaddr, _) => vaddr,
             Self::Physical(paddr, _) => paddr,
         }
     }
 
-    pub fn get_table_index(&self) -> usize {
-        match *self {
-            Self::Virtual(vaddr, _) => ((vaddr >> PTXSHIFT) & 0x3FF) as usize,
-            Self::Physical(_, _) => 0,
-        }
-    }
-
     pub fn get_ptr(&self) -> *mut u32 {
         match *self {
             Self::Virtual(_, ptr) => ptr as *mut u32,
@@ -181,10 +358,11 @@ impl Address {
         }
     }
 
-    pub fn get_offset(&self) -> u32 {
+    pub fn get_offset(&self, geo: &Geometry) -> u64 {
+        let mask = geo.page_size as u64 - 1;
         match *self {
-            Self::Virtual(vaddr, _) => vaddr & 0xFFF,
-            Self::Physical(paddr, _) => paddr & 0xFFF,
+            Self::Virtual(vaddr, _) => vaddr & mask,
+            Self::Physical(paddr, _) => paddr & mask,
         }
     }
 }
@@ -0,0 +1,391 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::alloc::Frames;
+
+// A page replacement policy picks which resident user frame to evict when
+// the free list runs dry. The allocator reports frames as they become
+// resident, are referenced and are freed; `victim` must only return frames
+// that `Frames::evictable` allows and should stop tracking the frame it returns.
+pub trait ReplacementPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    fn insert(&mut self, ppn: u32);
+
+    fn remove(&mut self, ppn: u32);
+
+    fn touch(&mut self, _ppn: u32) {}
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32>;
+}
+
+// The built-in policies, so a policy can be picked by name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PolicyKind {
+    Fifo,
+    Lru,
+    Clock,
+    WsClock,
+    Random,
+}
+
+impl PolicyKind {
+    pub const ALL: [PolicyKind; 5] = [Self::Fifo, Self::Lru, Self::Clock, Self::WsClock, Self::Random];
+
+    // `window` is the working set window WSClock keeps, in references.
+    pub fn build(&self, seed: Option<u64>, window: u64) -> Box<dyn ReplacementPolicy> {
+        match *self {
+            Self::Fifo => Box::new(Fifo::new()),
+            Self::Lru => Box::new(Lru::new()),
+            Self::Clock => Box::new(Clock::new()),
+            Self::WsClock => Box::new(WsClock::new(window)),
+            Self::Random => match seed {
+                Some(seed) => Box::new(Random::seeded(seed)),
+                None => Box::new(Random::new()),
+            },
+        }
+    }
+}
+
+impl FromStr for PolicyKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fifo" => Ok(Self::Fifo),
+            "lru" => Ok(Self::Lru),
+            "clock" => Ok(Self::Clock),
+            "wsclock" => Ok(Self::WsClock),
+            "random" => Ok(Self::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+// Evicts frames in the order they became resident.
+#[derive(Default)]
+pub struct Fifo {
+    queue: VecDeque<u32>,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn name(&self) -> &'static str { "fifo" }
+
+    fn insert(&mut self, ppn: u32) {
+        self.queue.push_back(ppn);
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        self.queue.retain(|&p| p != ppn);
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        let idx = self.queue.iter().position(|&p| frames.evictable(p))?;
+        self.queue.remove(idx)
+    }
+}
+
+// Evicts the frame that was referenced least recently.
+#[derive(Default)]
+pub struct Lru {
+    queue: VecDeque<u32>,
+}
+
+impl Lru {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn name(&self) -> &'static str { "lru" }
+
+    fn insert(&mut self, ppn: u32) {
+        self.queue.push_back(ppn);
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        self.queue.retain(|&p| p != ppn);
+    }
+
+    fn touch(&mut self, ppn: u32) {
+        if let Some(idx) = self.queue.iter().position(|&p| p == ppn) {
+            self.queue.remove(idx);
+            self.queue.push_back(ppn);
+        }
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        let idx = self.queue.iter().position(|&p| frames.evictable(p))?;
+        self.queue.remove(idx)
+    }
+}
+
+// Second chance: sweeps the resident frames like a clock hand, clearing
+// the accessed bit of every referenced frame and evicting the first one
+// found with the bit already clear.
+#[derive(Default)]
+pub struct Clock {
+    ring: Vec<u32>,
+    hand: usize,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for Clock {
+    fn name(&self) -> &'static str { "clock" }
+
+    fn insert(&mut self, ppn: u32) {
+        // new frames go just behind the hand so they are examined last
+        self.ring.insert(self.hand, ppn);
+        self.hand = (self.hand + 1) % self.ring.len();
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        if let Some(idx) = self.ring.iter().position(|&p| p == ppn) {
+            self.ring.remove(idx);
+            if idx < self.hand {
+                self.hand -= 1;
+            }
+            if self.hand >= self.ring.len() {
+                self.hand = 0;
+            }
+        }
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        if !self.ring.iter().any(|&p| frames.evictable(p)) {
+            return None;
+        }
+        // two sweeps are enough: the first clears every accessed bit
+        for _ in 0..2 * self.ring.len() {
+            let ppn = self.ring[self.hand];
+            if frames.evictable(ppn) {
+                if frames.accessed(ppn) {
+                    frames.clear_accessed(ppn);
+                } else {
+                    self.ring.remove(self.hand);
+                    if self.hand >= self.ring.len() {
+                        self.hand = 0;
+                    }
+                    return Some(ppn);
+                }
+            }
+            self.hand = (self.hand + 1) % self.ring.len();
+        }
+        None
+    }
+}
+
+// WSClock: sweeps the resident frames like Clock, but evicts the first
+// one whose page has left the working set, going unreferenced for more
+// than `window` references, and would rather evict a clean page than
+// one that has to be written out first. The hand notes when it last saw
+// each frame's accessed bit set.
+pub struct WsClock {
+    ring: Vec<u32>,
+    hand: usize,
+    window: u64,
+    // the reference count when the hand last saw each frame in use, by
+    // the machine's clock so ages match what the other policies see
+    last_use: HashMap<u32, u64>,
+}
+
+impl WsClock {
+    pub fn new(window: u64) -> Self {
+        Self {
+            ring: Vec::new(),
+            hand: 0,
+            window,
+            last_use: HashMap::new(),
+        }
+    }
+
+    fn age(&self, ppn: u32, now: u64) -> u64 {
+        now.saturating_sub(self.last_use.get(&ppn).copied().unwrap_or(now))
+    }
+
+    fn take(&mut self, idx: usize) -> u32 {
+        let ppn = self.ring.remove(idx);
+        self.last_use.remove(&ppn);
+        if idx < self.hand {
+            self.hand -= 1;
+        }
+        if self.hand >= self.ring.len() {
+            self.hand = 0;
+        }
+        ppn
+    }
+}
+
+impl ReplacementPolicy for WsClock {
+    fn name(&self) -> &'static str { "wsclock" }
+
+    fn insert(&mut self, ppn: u32) {
+        self.ring.insert(self.hand, ppn);
+        self.hand = (self.hand + 1) % self.ring.len();
+        self.last_use.remove(&ppn);
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        if let Some(idx) = self.ring.iter().position(|&p| p == ppn) {
+            self.take(idx);
+        }
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        if !self.ring.iter().any(|&p| frames.evictable(p)) {
+            return None;
+        }
+        let now = frames.now() as u64;
+        let mut old_dirty = None;
+        for _ in 0..self.ring.len() {
+            let ppn = self.ring[self.hand];
+            if frames.evictable(ppn) {
+                // a frame the hand reaches for the first time came in just now
+                let last = *self.last_use.entry(ppn).or_insert(now);
+                if frames.accessed(ppn) {
+                    frames.clear_accessed(ppn);
+                    self.last_use.insert(ppn, now);
+                } else if now - last > self.window {
+                    if !frames.dirty(ppn) {
+                        return Some(self.take(self.hand));
+                    }
+                    old_dirty.get_or_insert(ppn);
+                }
+            }
+            self.hand = (self.hand + 1) % self.ring.len();
+        }
+        // every page is still in the working set, or dirty: take the
+        // first dirty one that has left it, or else the oldest
+        let ppn = match old_dirty {
+            Some(ppn) => ppn,
+            None => self.ring.iter()
+                .copied()
+                .filter(|&p| frames.evictable(p))
+                .max_by_key(|&p| self.age(p, now))?
+        };
+        let idx = self.ring.iter().position(|&p| p == ppn)?;
+        Some(self.take(idx))
+    }
+}
+
+// Evicts a uniformly chosen evictable frame.
+pub struct Random {
+    frames: Vec<u32>,
+    rng: StdRng,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            frames: Vec::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn name(&self) -> &'static str { "random" }
+
+    fn insert(&mut self, ppn: u32) {
+        self.frames.push(ppn);
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        self.frames.retain(|&p| p != ppn);
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        let candidates: Vec<usize> = (0..self.frames.len())
+            .filter(|&i| frames.evictable(self.frames[i]))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let idx = candidates[self.rng.gen_range(0..candidates.len())];
+        Some(self.frames.swap_remove(idx))
+    }
+}
+
+// Belady's MIN: evicts the frame whose page is referenced again furthest
+// in the future, or never. Knowing the future means it only runs offline,
+// on a reference string recorded from an earlier run on a fresh machine,
+// whose references line up with those the machine counts.
+pub struct Opt {
+    frames: Vec<u32>,
+    // when each (pid, page) is referenced, in order
+    uses: HashMap<(u32, u64), Vec<usize>>,
+}
+
+impl Opt {
+    // `refs` holds the pid and page address of every reference in order.
+    pub fn new(refs: &[(u32, u64)]) -> Self {
+        let mut uses: HashMap<(u32, u64), Vec<usize>> = HashMap::new();
+        for (i, &page) in refs.iter().enumerate() {
+            uses.entry(page).or_default().push(i);
+        }
+        Self {
+            frames: Vec::new(),
+            uses,
+        }
+    }
+
+    // The next reference to any page the frame holds, which after a fork
+    // may be in any of the processes sharing it.
+    fn next_use(&self, frames: &Frames, ppn: u32) -> usize {
+        let now = frames.now();
+        frames.mappings(ppn).iter()
+            .filter_map(|m| {
+                let uses = self.uses.get(&(m.pid, m.va))?;
+                uses.get(uses.partition_point(|&i| i < now)).copied()
+            })
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+impl ReplacementPolicy for Opt {
+    fn name(&self) -> &'static str { "opt" }
+
+    fn insert(&mut self, ppn: u32) {
+        self.frames.push(ppn);
+    }
+
+    fn remove(&mut self, ppn: u32) {
+        self.frames.retain(|&p| p != ppn);
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<u32> {
+        let idx = (0..self.frames.len())
+            .filter(|&i| frames.evictable(self.frames[i]))
+            .max_by_key(|&i| self.next_use(frames, self.frames[i]))?;
+        Some(self.frames.swap_remove(idx))
+    }
+}
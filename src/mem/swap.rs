@@ -0,0 +1,91 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// tells apart the temporary swap files of simulators in one process
+static ANONYMOUS: AtomicUsize = AtomicUsize::new(0);
+
+// A swap area backed by a local file, split into page-sized slots.
+// Every slot keeps a count of the swapped-out entries (and the swap
+// cache frame, if any) still referring to it and is released at zero.
+pub struct SwapDevice {
+    path: PathBuf,
+    file: File,
+    page_size: usize,
+    refs: Vec<usize>,
+    free_list: Vec<u32>,
+}
+
+impl SwapDevice {
+    pub fn open<P: AsRef<Path>>(path: P, slots: u32, page_size: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.as_ref())?;
+        file.set_len(slots as u64 * page_size as u64)?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            file,
+            page_size,
+            refs: vec![0; slots as usize],
+            free_list: (0..slots).rev().collect(),
+        })
+    }
+
+    // A swap area in a temporary file that is removed as soon as it is
+    // open, so nothing is left behind and no two simulators share one.
+    pub fn anonymous(slots: u32, page_size: usize) -> io::Result<Self> {
+        let n = ANONYMOUS.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("rust-vmem-{}-{}.swap", process::id(), n));
+        let device = Self::open(&path, slots, page_size)?;
+        let _ = fs::remove_file(&path);
+        Ok(device)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn slots(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub fn used(&self) -> usize {
+        self.refs.len() - self.free_list.len()
+    }
+
+    pub fn alloc(&mut self) -> Option<u32> {
+        self.free_list.pop()
+    }
+
+    pub fn refs(&self, slot: u32) -> usize {
+        self.refs[slot as usize]
+    }
+
+    pub fn dup(&mut self, slot: u32) {
+        self.refs[slot as usize] += 1;
+    }
+
+    pub fn free(&mut self, slot: u32) {
+        let refs = &mut self.refs[slot as usize];
+        *refs -= 1;
+        if *refs == 0 {
+            self.free_list.push(slot);
+        }
+    }
+
+    pub fn read(&mut self, slot: u32, data: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(slot as u64 * self.page_size as u64))?;
+        self.file.read_exact(data)
+    }
+
+    pub fn write(&mut self, slot: u32, data: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(slot as u64 * self.page_size as u64))?;
+        self.file.write_all(data)
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::ptable::{Flag, PTE};
+
+// Which entry of a full set makes room for a new translation.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TlbPolicy {
+    Lru,
+    Fifo,
+    Random,
+}
+
+impl FromStr for TlbPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "fifo" => Ok(Self::Fifo),
+            "random" => Ok(Self::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TlbStats {
+    pub hits: u64,
+    pub misses: u64,
+    // single entries shot down after their page table entry changed
+    pub invalidations: u64,
+    // whole address spaces dropped, on a context switch or a fork
+    pub flushes: u64,
+}
+
+impl TlbStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct TlbEntry {
+    asid: u32,
+    va: u64,
+    // log2 of the page size the entry covers, so one entry of a large
+    // page stands in for a whole page table's worth of small ones
+    shift: usize,
+    // where the entry lives, so accessed and dirty bits can be written
+    // back without walking the directory
+    table: u32,
+    index: usize,
+    pte: PTE,
+    // insertion time for FIFO, time of last use for LRU
+    stamp: u64,
+}
+
+// A set-associative translation cache. Entries are tagged with the pid of
+// the process that loaded them. Without ASIDs every context switch flushes
+// the whole TLB, with them entries of other processes just never match.
+// Small and large pages share the sets; a lookup probes both sizes.
+pub struct Tlb {
+    sets: Vec<Vec<TlbEntry>>,
+    ways: usize,
+    shift: usize,
+    large_shift: usize,
+    policy: TlbPolicy,
+    tagged: bool,
+    clock: u64,
+    rng: StdRng,
+    stats: TlbStats,
+    per_asid: HashMap<u32, TlbStats>,
+}
+
+impl Tlb {
+    // A TLB with no entries is disabled and never hits.
+    pub fn new(entries: usize, ways: usize, page_size: usize, large_page_size: u64, policy: TlbPolicy, tagged: bool, seed: Option<u64>) -> Self {
+        let nsets = match ways {
+            0 => 0,
+            ways => entries / ways,
+        };
+        Self {
+            sets: vec![Vec::new(); nsets],
+            ways,
+            shift: page_size.trailing_zeros() as usize,
+            large_shift: large_page_size.trailing_zeros() as usize,
+            policy,
+            tagged,
+            clock: 0,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            stats: TlbStats::default(),
+            per_asid: HashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.sets.is_empty()
+    }
+
+    pub fn entries(&self) -> usize {
+        self.sets.len() * self.ways
+    }
+
+    pub fn ways(&self) -> usize {
+        self.ways
+    }
+
+    pub fn tagged(&self) -> bool {
+        self.tagged
+    }
+
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+
+    pub fn stats_for(&self, asid: u32) -> TlbStats {
+        self.per_asid.get(&asid).copied().unwrap_or_default()
+    }
+
+    // Bytes of address space the cached translations cover.
+    pub fn reach(&self) -> u64 {
+        self.sets.iter().flatten().map(|e| 1u64 << e.shift).sum()
+    }
+
+    pub fn reach_for(&self, asid: u32) -> u64 {
+        self.sets.iter().flatten()
+            .filter(|e| e.asid == asid)
+            .map(|e| 1u64 << e.shift)
+            .sum()
+    }
+
+    fn page(va: u64, shift: usize) -> u64 {
+        va >> shift << shift
+    }
+
+    fn set(&self, va: u64, shift: usize) -> usize {
+        (va >> shift) as usize % self.sets.len()
+    }
+
+    // The set and index of the entry translating `va` at the given size.
+    fn probe(&self, asid: u32, va: u64, shift: usize) -> Option<(usize, usize)> {
+        let page = Self::page(va, shift);
+        let set = self.set(page, shift);
+        self.sets[set].iter()
+            .position(|e| e.asid == asid && e.va == page && e.shift == shift)
+            .map(|way| (set, way))
+    }
+
+    // Returns the cached table, index and entry for `va`, if any.
+    pub fn lookup(&mut self, asid: u32, va: u64) -> Option<(u32, usize, PTE)> {
+        if !self.enabled() {
+            return None;
+        }
+        self.clock += 1;
+        let clock = self.clock;
+        let lru = self.policy == TlbPolicy::Lru;
+        let hit = self.probe(asid, va, self.shift)
+            .or_else(|| self.probe(asid, va, self.large_shift));
+        let found = hit.map(|(set, way)| {
+            let e = &mut self.sets[set][way];
+            if lru {
+                e.stamp = clock;
+            }
+            (e.table, e.index, e.pte)
+        });
+        let counters = self.per_asid.entry(asid).or_default();
+        match found {
+            Some(_) => {
+                self.stats.hits += 1;
+                counters.hits += 1;
+            },
+            None => {
+                self.stats.misses += 1;
+                counters.misses += 1;
+            }
+        }
+        found
+    }
+
+    // Caches a translation, replacing any older one for the same page.
+    pub fn insert(&mut self, asid: u32, va: u64, table: u32, index: usize, pte: PTE) {
+        if !self.enabled() {
+            return;
+        }
+        let shift = if pte.get_flag(Flag::PageSize) { self.large_shift } else { self.shift };
+        let va = Self::page(va, shift);
+        let set = self.set(va, shift);
+        self.clock += 1;
+        let entry = TlbEntry { asid, va, shift, table, index, pte, stamp: self.clock };
+
+        if let Some(e) = self.sets[set].iter_mut().find(|e| e.asid == asid && e.va == va && e.shift == shift) {
+            // a refresh keeps its place in the FIFO order
+            let stamp = match self.policy {
+                TlbPolicy::Fifo => e.stamp,
+                _ => entry.stamp,
+            };
+            *e = TlbEntry { stamp, ..entry };
+            return;
+        }
+        if self.sets[set].len() < self.ways {
+            self.sets[set].push(entry);
+            return;
+        }
+        let victim = match self.policy {
+            TlbPolicy::Random => self.rng.gen_range(0..self.ways),
+            TlbPolicy::Lru | TlbPolicy::Fifo => {
+                let ways = &self.sets[set];
+                (0..ways.len()).min_by_key(|&i| ways[i].stamp).unwrap()
+            }
+        };
+        self.sets[set][victim] = entry;
+    }
+
+    // Drops the translation for one page, e.g. after a fault rewrote it.
+    // Only a translation that was cached counts as an invalidation.
+    pub fn invalidate(&mut self, asid: u32, va: u64) {
+        if !self.enabled() {
+            return;
+        }
+        for shift in [self.shift, self.large_shift] {
+            if let Some((set, way)) = self.probe(asid, va, shift) {
+                self.sets[set].remove(way);
+                self.stats.invalidations += 1;
+                self.per_asid.entry(asid).or_default().invalidations += 1;
+            }
+        }
+    }
+
+    // Drops every translation of one address space.
+    pub fn flush_asid(&mut self, asid: u32) {
+        if !self.enabled() {
+            return;
+        }
+        for set in self.sets.iter_mut() {
+            set.retain(|e| e.asid != asid);
+        }
+        self.stats.flushes += 1;
+        self.per_asid.entry(asid).or_default().flushes += 1;
+    }
+
+    pub fn flush(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+        for set in self.sets.iter_mut() {
+            set.clear();
+        }
+        self.stats.flushes += 1;
+    }
+
+    // Called whenever another process starts running.
+    pub fn context_switch(&mut self) {
+        if !self.tagged {
+            self.flush();
+        }
+    }
+}
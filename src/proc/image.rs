@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mem::mmap::Prot;
+use crate::sim::error::SimError;
+
+// Pointers on the initial stack are a word wide, like every value.
+pub const WORD: u64 = 8;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    pub start: u64,
+    pub len: u64,
+    pub prot: Prot,
+    // copied to the start of the segment, the rest of it reads as zero
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Syntax { line: usize, text: String },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read image: {}", err),
+            Self::Syntax { line, text } => write!(f, "line {}: cannot parse \"{}\"", line, text),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+// What `Simulator::exec` loads in place of a process's address space: the
+// segments of a program with their initial data, and a stack holding its
+// arguments and environment the way a System V loader lays them out.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Image {
+    segments: Vec<Segment>,
+    // the top of the stack and its size, it grows down
+    stack: Option<(u64, u64)>,
+    argv: Vec<String>,
+    envp: Vec<String>,
+}
+
+impl Image {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn segment(mut self, start: u64, len: u64, prot: Prot, data: &[u8]) -> Self {
+        self.segments.push(Segment { start, len, prot, data: data.to_vec() });
+        self
+    }
+
+    pub fn stack(mut self, top: u64, size: u64) -> Self {
+        self.stack = Some((top, size));
+        self
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.argv.push(arg.to_string());
+        self
+    }
+
+    pub fn env(mut self, var: &str) -> Self {
+        self.envp.push(var.to_string());
+        self
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    // The lowest address of the stack and its size.
+    pub fn stack_range(&self) -> Option<(u64, u64)> {
+        self.stack.and_then(|(top, size)| Some((top.checked_sub(size)?, size)))
+    }
+
+    // Where the stack pointer starts and the bytes from there up to the
+    // top: argc, the argv pointers, a null, the envp pointers, another
+    // null, then the strings themselves.
+    pub fn initial_stack(&self) -> Option<(u64, Vec<u8>)> {
+        let (top, _) = self.stack?;
+        let strings: Vec<&String> = self.argv.iter().chain(&self.envp).collect();
+        let pointers = (self.argv.len() + self.envp.len() + 3) as u64 * WORD;
+        let text: u64 = strings.iter().map(|s| s.len() as u64 + 1).sum();
+        let sp = top.saturating_sub(pointers + text) & !15;
+
+        let mut words = vec![self.argv.len() as u64];
+        let mut addr = sp + pointers;
+        for list in [&self.argv, &self.envp] {
+            for s in list {
+                words.push(addr);
+                addr += s.len() as u64 + 1;
+            }
+            words.push(0);
+        }
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        for s in strings {
+            bytes.extend_from_slice(s.as_bytes());
+            bytes.push(0);
+        }
+        Some((sp, bytes))
+    }
+
+    // Checks the image can be loaded before anything is torn down: every
+    // range is page aligned and apart from the others, data fits in its
+    // segment and the arguments fit on the stack.
+    pub fn validate(&self, page_size: u64) -> Result<(), SimError> {
+        let mut ranges = Vec::new();
+        for seg in &self.segments {
+            if seg.len == 0 || seg.start % page_size != 0 {
+                return Err(SimError::InvalidArgument("segment is empty or not aligned to a page"));
+            }
+            if seg.data.len() as u64 > seg.len {
+                return Err(SimError::InvalidArgument("segment data is longer than the segment"));
+            }
+            ranges.push((seg.start, seg.start.checked_add(seg.len)
+                .ok_or(SimError::InvalidArgument("segment wraps around"))?));
+        }
+        match self.stack {
+            Some((top, size)) => {
+                if size == 0 || size > top || top % page_size != 0 || size % page_size != 0 {
+                    return Err(SimError::InvalidArgument("stack is empty or not aligned to a page"));
+                }
+                let (sp, bytes) = self.initial_stack().unwrap_or_default();
+                if top - sp > size || bytes.len() as u64 > top - sp {
+                    return Err(SimError::InvalidArgument("arguments do not fit on the stack"));
+                }
+                ranges.push((top - size, top));
+            },
+            None if !self.argv.is_empty() || !self.envp.is_empty() =>
+                return Err(SimError::InvalidArgument("arguments need a stack")),
+            None => {}
+        }
+        ranges.sort_unstable();
+        if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            return Err(SimError::InvalidArgument("segments overlap"));
+        }
+        Ok(())
+    }
+
+    // Reads an image description, one item per line:
+    //
+    //   segment <start> <len> <rwx> [hex data]
+    //   stack <top> <size>
+    //   arg <word>
+    //   env <name=value>
+    //
+    // Numbers are decimal or 0x hex, and a # starts a comment.
+    pub fn parse(text: &str) -> Result<Self, ImageError> {
+        let mut image = Self::new();
+        for (i, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            let syntax = || ImageError::Syntax { line: i + 1, text: line.to_string() };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            image = match words.as_slice() {
+                [] => image,
+                ["segment", start, len, prot, data @ ..] if data.len() <= 1 => {
+                    let data = match data.first() {
+                        Some(hex) => parse_bytes(hex).ok_or_else(syntax)?,
+                        None => Vec::new(),
+                    };
+                    let start = parse_num(start).ok_or_else(syntax)?;
+                    let len = parse_num(len).ok_or_else(syntax)?;
+                    image.segment(start, len, prot.parse().map_err(|_| syntax())?, &data)
+                },
+                ["stack", top, size] => image.stack(parse_num(top).ok_or_else(syntax)?, parse_num(size).ok_or_else(syntax)?),
+                ["arg", arg] => image.arg(arg),
+                ["env", var] => image.env(var),
+                _ => return Err(syntax()),
+            };
+        }
+        Ok(image)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+fn parse_num(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
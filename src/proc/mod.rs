@@ -1 +1,4 @@
-pub mod proc;
\ No newline at end of file
+pub mod image;
+#[allow(clippy::module_inception)]
+pub mod proc;
+pub mod ws;
@@ -1,421 +1,1386 @@
-use crate::mem::ptable::{PTE, Flag, Virtual, Physical, Address};
-use crate::mem::alloc::{self, Page};
-use crate::sim::check::{ValueType, DataType};
+use crate::mem::ptable::{PTE, Flag, Virtual, Physical, Address, Geometry};
+use crate::mem::alloc::{Memory, Mapping, Page};
+use crate::mem::mmap::{Prot, Sharing};
+use crate::sim::check::{ValueType, DataType, Access};
+use crate::sim::config::Verbosity;
+use crate::sim::error::SimError;
+use crate::sim::event::EventKind;
+use crate::sim::stats::Stats;
+use super::ws::{Pff, WorkingSet};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
 
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::cell::{RefMut, RefCell};
-
-#[derive(PartialEq, Eq)]
-enum ProcessState {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProcessState {
     Running,
-    Terminated,
     Sleeping,
+    // exited, with its memory freed, until the parent reaps it
+    Zombie,
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Running => f.pad("running"),
+            Self::Sleeping => f.pad("sleeping"),
+            Self::Zombie => f.pad("zombie"),
+        }
+    }
+}
+
+// A page table below the directory and where it sits in the tree.
+#[derive(Copy, Clone)]
+struct Table {
+    ppn: u32,
+    level: usize,
+    // the lowest virtual address the table maps
+    va: u64,
+}
+
+// A file mapped into the address space, page aligned.
+#[derive(Copy, Clone)]
+struct Region {
+    start: u64,
+    len: u64,
+    file: u32,
+    // where in the file the region starts
+    offset: u64,
+    sharing: Sharing,
+}
+
+impl Region {
+    fn contains(&self, va: u64) -> bool {
+        va >= self.start && va - self.start < self.len
+    }
 }
 
 pub struct Process {
     pid: u32,
+    // the process that forked it, none for pid 0
+    parent: Option<u32>,
     state: ProcessState,
-    pgdir: Rc<RefCell<Page>>,
-    tables: Vec<Rc<RefCell<Page>>>,
-    phys_pages: HashMap<u32, Rc<RefCell<Page>>>,
-    debug: bool,
+    // what it exited with, once it has
+    status: Option<u8>,
+    // the heap runs from its start up to the program break
+    heap_start: u64,
+    brk: u64,
+    pgdir: u32,
+    tables: Vec<Table>,
+    regions: Vec<Region>,
+    // registered variables, each keeping the page it lies on mapped
+    vars: BTreeSet<u64>,
+    geo: Geometry,
+    debug: Verbosity,
+    ws: WorkingSet,
+    pff: Option<Pff>,
 }
 
-impl Process {  
-    pub fn new(pid: u32, debug: bool) -> Self {
-        let pgdir = match alloc::kalloc() {
-            Some(dir) => dir,
-            None => panic!("Out of memory")
-        };
-        Self {
-            pid: pid,
+impl Process {
+    pub fn new(mem: &mut Memory, pid: u32, geo: Geometry, debug: Verbosity) -> Result<Self, SimError> {
+        let pgdir = mem.kalloc().ok_or(SimError::OutOfMemory)?;
+        Ok(Self {
+            pid,
+            parent: None,
             state: ProcessState::Sleeping,
-            pgdir: Rc::new(RefCell::new(*pgdir)),
+            status: None,
+            heap_start: 0,
+            brk: 0,
+            pgdir,
             tables: Vec::new(),
-            phys_pages: HashMap::new(),
+            regions: Vec::new(),
+            vars: BTreeSet::new(),
+            geo,
             debug,
-        }
+            ws: WorkingSet::default(),
+            pff: None,
+        })
     }
 
     pub fn pid(&self) -> u32 {
         self.pid
     }
 
+    pub fn parent(&self) -> Option<u32> {
+        self.parent
+    }
+
+    // Hands the process to a new parent when its own one exits.
+    pub fn reparent(&mut self, parent: u32) {
+        self.parent = Some(parent);
+    }
+
+    // The exit status, if the process is a zombie.
+    pub fn status(&self) -> Option<u8> {
+        self.status
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    pub fn is_zombie(&self) -> bool {
+        self.state == ProcessState::Zombie
+    }
+
+    // Starts an empty heap at `start`.
+    pub fn set_heap(&mut self, start: u64) {
+        self.heap_start = start;
+        self.brk = start;
+    }
+
+    // Where the heap starts and the program break.
+    pub fn heap(&self) -> (u64, u64) {
+        (self.heap_start, self.brk)
+    }
+
+    // Moves the program break to `addr`, like brk. Pages the heap grows
+    // into are mapped to the zero page, and the ones it shrinks out of
+    // are unmapped, freeing their frames.
+    pub fn brk(&mut self, mem: &mut Memory, addr: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        if addr < self.heap_start {
+            return Err(SimError::InvalidArgument("break below the start of the heap"));
+        }
+        let page_size = self.geo.page_size() as u64;
+        let old_end = self.brk.div_ceil(page_size) * page_size;
+        let new_end = addr.div_ceil(page_size) * page_size;
+        if new_end > old_end {
+            self.map_zero(mem, old_end, new_end - old_end)?;
+        } else if new_end < old_end {
+            self.munmap(mem, new_end, old_end - new_end)?;
+        }
+        self.brk = addr;
+        Ok(())
+    }
+
+    // Maps `len` bytes at `start` to the zero page, as anonymous memory
+    // that is only given frames when written. Nothing is mapped if any
+    // page in the range already is.
+    pub fn map_zero(&mut self, mem: &mut Memory, start: u64, len: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        let page_size = self.geo.page_size() as u64;
+        let end = start.checked_add(len).ok_or(SimError::InvalidArgument("range wraps around"))?;
+        let pages: Vec<u64> = (start..end).step_by(page_size as usize).collect();
+        for &va in &pages {
+            self.check_canonical(mem, Address::Virtual(va, 0))?;
+            if self.mapped(mem, Virtual::new(va, 0)) {
+                return Err(SimError::AlreadyMapped(va));
+            }
+        }
+        let pa = Physical::new(0, mem.zero_page() as *const Page as usize);
+        for (i, &va) in pages.iter().enumerate() {
+            let mapped = self.map(mem, Virtual::new(va, 0), pa, &[Flag::User, Flag::Zero, Flag::CopyOnWrite, Flag::NoExecute]);
+            if let Err(err) = mapped {
+                // no frame for a page table, so take back what was mapped
+                self.munmap(mem, start, i as u64 * page_size)?;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // Tracks the working set from now on, and lets PFF bound the frames
+    // the process keeps if given. A forked child starts afresh with both.
+    pub fn watch(&mut self, ws: WorkingSet, pff: Option<Pff>) {
+        self.ws = ws;
+        self.pff = pff;
+    }
+
+    pub fn working_set(&self) -> &WorkingSet {
+        &self.ws
+    }
+
+    // Frames the process may keep resident, if PFF has set a bound.
+    pub fn frame_limit(&self) -> Option<usize> {
+        self.pff.as_ref().and_then(Pff::limit)
+    }
+
+    // Counts a reference the process made, sampling its working set when
+    // one is due and logging its size. Under PFF the bound is adjusted at
+    // every sample, and a fault that takes the process over it evicts one
+    // of its own pages, so it only ever replaces locally.
+    pub fn referenced(&mut self, mem: &mut Memory, faulted: bool) {
+        if let Some(pff) = self.pff.as_mut() {
+            pff.count(faulted);
+        }
+        if !self.ws.tick() {
+            if faulted {
+                if let Some(limit) = self.frame_limit() {
+                    mem.shrink(self.pid, limit);
+                }
+            }
+            return;
+        }
+        self.ws.sample(mem.sample_accessed(self.pid));
+        let limit = match self.pff.as_mut() {
+            Some(pff) => {
+                let limit = pff.adjust(mem.resident_pages(self.pid), self.ws.size(), mem.nframes() as usize);
+                mem.shrink(self.pid, limit);
+                limit
+            },
+            None => 0
+        };
+        mem.record(EventKind::WorkingSet {
+            refs: self.ws.vtime(),
+            pages: self.ws.size() as u64,
+            resident: mem.resident_pages(self.pid) as u64,
+            limit: limit as u64,
+        });
+    }
+
     pub fn wake_up(&mut self) {
         self.state = ProcessState::Running;
     }
 
     pub fn yieldk(&mut self) {
-        self.state = ProcessState::Sleeping;
+        if self.state == ProcessState::Running {
+            self.state = ProcessState::Sleeping;
+        }
+    }
+
+    // Only the running process maps, protects or forks, never one that
+    // is asleep or a zombie whose tables are freed.
+    fn check_running(&self) -> Result<(), SimError> {
+        match self.state {
+            ProcessState::Running => Ok(()),
+            _ => Err(SimError::NotRunning(self.pid))
+        }
+    }
+
+    // Frees everything the process holds and leaves it a zombie with
+    // `status` for its parent to collect.
+    pub fn exit(&mut self, mem: &mut Memory, status: u8) {
+        self.state = ProcessState::Zombie;
+        self.status = Some(status);
+        self.release(mem);
     }
 
-    pub fn kill(&mut self) {
-        self.state = ProcessState::Terminated;
+    // Swaps the address space for an empty one, for exec to load a new
+    // image into. Frames still shared copy-on-write since a fork are
+    // dropped like any others, and the working set starts over.
+    pub fn exec(&mut self, mem: &mut Memory) -> Result<(), SimError> {
+        self.check_running()?;
+        // a mapping that cannot be written back keeps the old image
+        for region in self.regions.clone() {
+            self.msync(mem, region.start, region.len)?;
+        }
+        let pgdir = mem.kalloc().ok_or(SimError::OutOfMemory)?;
+        self.release(mem);
+        self.pgdir = pgdir;
+        self.ws = self.ws.fresh();
+        self.pff = self.pff.as_ref().map(Pff::fresh);
+        Ok(())
+    }
+
+    // Frees every page, table and mapping the process holds, along with
+    // its page directory.
+    fn release(&mut self, mem: &mut Memory) {
+        mem.tlb_mut().flush_asid(self.pid);
+
+        // nothing can stop an exit, so a page that fails to write back
+        // is dropped and the failure recorded
+        for region in self.regions.clone() {
+            for va in (region.start..region.start + region.len).step_by(self.geo.page_size()) {
+                if let Some((_, _, pte)) = self.locate(mem, Address::Virtual(va, 0)) {
+                    let ppn = pte.get_ppn() as u32;
+                    if !pte.get_flag(Flag::Zero) && mem.writeback(ppn).is_err() {
+                        mem.record(EventKind::WritebackFailed { ppn });
+                    }
+                }
+            }
+        }
 
         // free all physical pages
-        for page_ref in self.phys_pages.values() {
-            let mut page: RefMut<_> = page_ref.borrow_mut();
-            if page.ref_count() == 0 {
+        for (_, _, pde) in self.large_pages(mem) {
+            if pde.get_flag(Flag::Zero) {
                 continue;
             }
-            if page.ref_count() > 1 {
-                page.decrement_refs();
+            let ppn = pde.get_ppn() as u32;
+            if mem.frame(ppn).ref_count() > 1 {
+                mem.frame_mut(ppn).decrement_refs();
             } else {
-                alloc::kfree(&*page);
+                mem.kfree(ppn);
+            }
+        }
+        for table in self.tables_at(self.geo.levels() - 1) {
+            for ptx in 0..self.geo.entries() {
+                let pte = mem.read_pte(table, ptx);
+                if pte.get_flag(Flag::Swapped) {
+                    mem.swap_free(pte.get_ppn() as u32);
+                    continue;
+                }
+                if !pte.get_flag(Flag::Present) || pte.get_flag(Flag::Zero) {
+                    continue;
+                }
+                let ppn = pte.get_ppn() as u32;
+                mem.untrack(ppn, table, ptx);
+                if mem.frame(ppn).ref_count() > 1 {
+                    mem.frame_mut(ppn).decrement_refs();
+                } else {
+                    mem.kfree(ppn);
+                }
             }
         }
-        self.phys_pages.clear();
 
-        // free the directory
-        let pgdir_ref = self.pgdir.borrow();
-        alloc::kfree(&*pgdir_ref);
-        for page in &self.tables {
-            let page_ref = page.borrow();
-            alloc::kfree(&*page_ref);
+        for region in self.regions.drain(..) {
+            mem.close_file(region.file);
+        }
+        self.vars.clear();
+
+        // free the directory and every table below it
+        mem.kfree(self.pgdir);
+        for table in &self.tables {
+            mem.kfree(table.ppn);
         }
         self.tables.clear();
     }
 
-    pub fn mapped(&self, vaddr: Virtual) -> bool {
-        match self.walk(vaddr) {
-            Some(_) => true,
+    // The frames of every table at `level`, the directory being level 0.
+    fn tables_at(&self, level: usize) -> Vec<u32> {
+        match level {
+            0 => vec![self.pgdir],
+            level => self.tables.iter().filter(|t| t.level == level).map(|t| t.ppn).collect(),
+        }
+    }
+
+    // The directory entries that map large pages, with where they live.
+    fn large_pages(&self, mem: &Memory) -> Vec<(u32, usize, PTE)> {
+        let mut pages = Vec::new();
+        for table in self.tables_at(self.geo.levels() - 2) {
+            for pdx in 0..self.geo.entries() {
+                let pde = mem.read_pte(table, pdx);
+                if pde.get_flag(Flag::PageSize) {
+                    pages.push((table, pdx, pde));
+                }
+            }
+        }
+        pages
+    }
+
+    pub fn mapped(&self, mem: &Memory, vaddr: Virtual) -> bool {
+        // a zombie's directory is gone
+        if self.is_zombie() {
+            return false;
+        }
+        match self.entry(mem, vaddr.get()) {
+            Some((_, _, pte)) =>
+                pte.get_flag(Flag::Present) || pte.get_flag(Flag::Swapped) || pte.get_flag(Flag::File),
             None => false
         }
     }
 
-    pub fn map(&mut self, vaddr: Virtual, paddr: Physical, flags: &[Flag]) {
-        let mut d = self.pgdir.borrow_mut();
+    pub fn map(&mut self, mem: &mut Memory, vaddr: Virtual, paddr: Physical, flags: &[Flag]) -> Result<(), SimError> {
+        self.check_running()?;
+        let va = vaddr.get();
+        let pa = paddr.get();
+        self.check_canonical(mem, va)?;
+        let table = self.table_for(mem, va, self.geo.levels() - 1)?;
+
+        let ptx = va.get_index(&self.geo, self.geo.levels() - 1);
+        let mut pte = mem.read_pte(table, ptx);
 
+        let ppn = (pa.get_address(&self.geo) >> self.geo.ptxshift()) as u32;
+        pte.set(PTE::new(ppn).get_address(), flags);
+        pte.set_flag(Flag::Present);
+        pte.set_flag(Flag::Accessed);
+        mem.write_pte(table, ptx, pte);
+        mem.tlb_mut().invalidate(self.pid, va.get());
+        mem.track(pte.get_ppn() as u32, self.mapping(va, table, ptx));
+        mem.record(EventKind::Map { va: va.get_address(&self.geo), ppn: pte.get_ppn() as u32 });
+        Ok(())
+    }
+
+    // Maps the whole large page around `vaddr` straight from the table
+    // above the leaves, so no page table is needed below it.
+    pub fn map_large(&mut self, mem: &mut Memory, vaddr: Virtual, paddr: Physical, flags: &[Flag]) -> Result<(), SimError> {
+        self.check_running()?;
         let va = vaddr.get();
         let pa = paddr.get();
+        self.check_canonical(mem, va)?;
+        let level = self.geo.levels() - 2;
+        let table = self.table_for(mem, va, level)?;
 
-        // page walk
-        let pdx = va.get_dir_index();
-        let ptx = va.get_table_index();
-        
-        let raw_pd_data = d.read::<u32>(pdx * 4);
-        let mut pde = PTE::from(raw_to_u32(raw_pd_data));
-        if !pde.get_flag(Flag::Present) {
-            // allocate page
-            if let Some(pg) = alloc::kalloc() {
-                pde.set(PTE::new(self.tables.len() as u32).get_address(), &[
-                    Flag::Present, Flag::Protected, Flag::Writable, Flag::Accessed
-                ]);
-                d.write::<u32>(pdx * 4, u32_to_raw(pde.get()).as_ref());
-                self.tables.push(Rc::new(RefCell::new(*pg)));
-            } else {
-                // eventually replace with page replacement call,
-                // then panic only if that fails
-                panic!("Out of memory");
+        let pdx = va.get_index(&self.geo, level);
+        let mut pde = mem.read_pte(table, pdx);
+        let base = va.get() & !(self.geo.large_page_size() - 1);
+        if pde.get_flag(Flag::Present) {
+            // part of the region is already mapped through a page table
+            return Err(SimError::AlreadyMapped(base));
+        }
+
+        let ppn = (pa.get_address(&self.geo) >> self.geo.ptxshift()) as u32;
+        pde.set(PTE::new(ppn).get_address(), flags);
+        pde.set_flag(Flag::Present);
+        pde.set_flag(Flag::Accessed);
+        pde.set_flag(Flag::PageSize);
+        mem.write_pte(table, pdx, pde);
+        mem.tlb_mut().invalidate(self.pid, va.get());
+        mem.track(ppn, self.mapping(va, table, pdx));
+        mem.record(EventKind::Map { va: base, ppn });
+        Ok(())
+    }
+
+    // Walks down to the table at `depth`, allocating missing tables on the
+    // way. A large page in the way means `va` is already mapped.
+    fn table_for(&mut self, mem: &mut Memory, va: Address, depth: usize) -> Result<u32, SimError> {
+        let mut table = self.pgdir;
+        for level in 0..depth {
+            let idx = va.get_index(&self.geo, level);
+            let mut pde = mem.read_pte(table, idx);
+            if pde.get_flag(Flag::PageSize) {
+                return Err(SimError::AlreadyMapped(va.get()));
+            }
+            if !pde.get_flag(Flag::Present) {
+                // allocate page
+                if let Some(ppn) = mem.kalloc() {
+                    pde.set(PTE::new(ppn).get_address(), &[
+                        Flag::Present, Flag::Protected, Flag::Writable, Flag::Accessed
+                    ]);
+                    mem.write_pte(table, idx, pde);
+                    self.tables.push(Table {
+                        ppn,
+                        level: level + 1,
+                        va: va.get() & !((1u64 << self.geo.shift(level)) - 1),
+                    });
+                } else {
+                    // page replacement could not free a frame either
+                    return Err(SimError::OutOfMemory);
+                }
+            }
+            table = pde.get_ppn() as u32;
+        }
+        Ok(table)
+    }
+
+    fn check_canonical(&self, mem: &mut Memory, va: Address) -> Result<(), SimError> {
+        if self.geo.canonical(va.get()) {
+            return Ok(());
+        }
+        mem.record(EventKind::InvalidAccess { va: va.get_address(&self.geo) });
+        Err(SimError::NonCanonical(va.get()))
+    }
+
+    // Walks from the directory down to the entry for `va`. Returns the
+    // last-level table, the index into it and the entry itself, along
+    // with the number of entries read on the way. A walk that meets a
+    // large page stops there and returns the directory entry instead.
+    fn walk(&self, mem: &Memory, va: Address) -> (Option<(u32, usize, PTE)>, usize) {
+        if !self.geo.canonical(va.get()) {
+            return (None, 0);
+        }
+        let last = self.geo.levels() - 1;
+        let mut table = self.pgdir;
+        for level in 0..last {
+            let idx = va.get_index(&self.geo, level);
+            let pde = mem.read_pte(table, idx);
+            if pde.get_flag(Flag::PageSize) {
+                return (Some((table, idx, pde)), level + 1);
             }
+            if !pde.get_flag(Flag::Present) {
+                return (None, level + 1);
+            }
+            table = pde.get_ppn() as u32;
         }
+        let ptx = va.get_index(&self.geo, last);
+        (Some((table, ptx, mem.read_pte(table, ptx))), last + 1)
+    }
 
-        let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-        let raw_data = pgtab.read::<u32>(ptx);
-        let mut pte = PTE::from(raw_to_u32(raw_data));
+    // Returns the page table and index holding the entry for `va`
+    // along with the entry itself, if it is present.
+    fn locate(&self, mem: &Memory, va: Address) -> Option<(u32, usize, PTE)> {
+        match self.entry(mem, va) {
+            Some((table, ptx, pte)) if pte.get_flag(Flag::Present) => Some((table, ptx, pte)),
+            _ => None
+        }
+    }
 
-        pte.set(pa.get_address(), flags);
+    // Like `locate`, but also returns entries that are not present.
+    fn entry(&self, mem: &Memory, va: Address) -> Option<(u32, usize, PTE)> {
+        self.walk(mem, va).0
+    }
+
+    // Finds the present entry for `va`, consulting the TLB before walking
+    // the page table and faulting a swapped-out page back in on a miss.
+    fn resolve(&self, mem: &mut Memory, va: Address) -> Result<Option<(u32, usize, PTE)>, SimError> {
+        if let Some(hit) = mem.tlb_mut().lookup(self.pid, va.get()) {
+            return Ok(Some(hit));
+        }
+        let (found, reads) = self.walk(mem, va);
+        mem.count_walk(self.pid, reads);
+        let (table, ptx, mut pte) = match found {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        if pte.get_flag(Flag::Swapped) {
+            self.swap_in(mem, va, table, ptx)?;
+            pte = mem.read_pte(table, ptx);
+        } else if pte.get_flag(Flag::File) {
+            self.file_in(mem, va, table, ptx)?;
+            pte = mem.read_pte(table, ptx);
+        }
+        if !pte.get_flag(Flag::Present) {
+            return Ok(None);
+        }
+        mem.tlb_mut().insert(self.pid, va.get(), table, ptx, pte);
+        Ok(Some((table, ptx, pte)))
+    }
+
+    // Faults the swapped-out page for `va` back into memory.
+    fn swap_in(&self, mem: &mut Memory, va: Address, table: u32, ptx: usize) -> Result<(), SimError> {
+        mem.swap_in(self.mapping(va, table, ptx))?;
+        if self.debug >= Verbosity::Faults {
+            let pgnum = va.translate(&self.geo).get_address(&self.geo);
+            println!("PGSWAP: 0x{:x}", pgnum);
+        }
+        Ok(())
+    }
+
+    // The frame `va` falls on and the offset into it. A large page spans
+    // a run of frames starting at the one its entry names.
+    fn frame_of(&self, pte: PTE, va: Address) -> (u32, usize) {
+        let ppn = pte.get_ppn() as u32;
+        let offset = va.get_offset(&self.geo) as usize;
+        if !pte.get_flag(Flag::PageSize) {
+            return (ppn, offset);
+        }
+        let frame = (va.get() & (self.geo.large_page_size() - 1)) >> self.geo.ptxshift();
+        (ppn + frame as u32, offset)
+    }
+
+    // The first address of the page, small or large, that `pte` maps.
+    fn page_base(&self, pte: PTE, va: Address) -> u64 {
+        if pte.get_flag(Flag::PageSize) {
+            va.get() & !(self.geo.large_page_size() - 1)
+        } else {
+            va.get_address(&self.geo)
+        }
+    }
+
+    // A frame for a private copy of the page `pte` maps, or a whole run
+    // of them for a large page.
+    fn kalloc(&self, mem: &mut Memory, pte: PTE) -> Option<u32> {
+        if pte.get_flag(Flag::PageSize) {
+            mem.kalloc_large()
+        } else {
+            mem.kalloc()
+        }
+    }
+
+    // Reads the page of a mapped file behind `va` in through the page
+    // cache. The entry keeps the permissions `map_file` gave it, so shared
+    // writable mappings get the cached frame writable and private ones
+    // copy it on the first write.
+    fn file_in(&self, mem: &mut Memory, va: Address, table: u32, ptx: usize) -> Result<(), SimError> {
+        let region = *self.region(va.get()).ok_or(SimError::InvalidAddress(va.get()))?;
+        let page = va.get_address(&self.geo);
+        let ppn = mem.file_page(region.file, region.offset + (page - region.start))?;
+
+        let mut pte = mem.read_pte(table, ptx);
+        pte.set_ppn(ppn);
+        pte.clear_flag(Flag::File);
         pte.set_flag(Flag::Present);
         pte.set_flag(Flag::Accessed);
-        pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
+        mem.write_pte(table, ptx, pte);
+        mem.track(ppn, self.mapping(va, table, ptx));
+        mem.record(EventKind::FileIn { va: page, ppn });
+
+        if self.debug >= Verbosity::Faults {
+            let pgnum = va.translate(&self.geo).get_address(&self.geo);
+            println!("PGFILE: 0x{:x}", pgnum);
+        }
+        Ok(())
     }
 
-    fn walk(&self, vaddr: Virtual) -> Option<PTE> {
-        let d = self.pgdir.borrow();
+    fn region(&self, va: u64) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(va))
+    }
 
-        let va = vaddr.get();
+    fn protection_fault(&self, mem: &mut Memory, va: Address) -> SimError {
+        mem.record(EventKind::InvalidAccess { va: va.get_address(&self.geo) });
+        SimError::ProtectionFault(va.get())
+    }
 
-        // page walk
-        let pdx = va.get_dir_index();
-        let ptx = va.get_table_index();
-        
-        let raw_pd_data = d.read::<u32>(pdx * 4);
-        let pde = PTE::from(raw_to_u32(raw_pd_data));
-        if !pde.get_flag(Flag::Present) {
-            return None;
+    // Sets what a page allows. A page that may be written but still
+    // shares its frame, or has none yet, is marked copy-on-write rather
+    // than writable, so the first write gets it a frame of its own.
+    // Like mprotect on x86, a page that may be written or executed may
+    // also be read, so only one that allows nothing keeps reads out.
+    fn set_prot(&self, mem: &Memory, va: u64, pte: &mut PTE, prot: Prot) {
+        if !prot.read && !prot.write && !prot.exec {
+            pte.set_flag(Flag::ProtNone);
+        } else {
+            pte.clear_flag(Flag::ProtNone);
+        }
+        if prot.exec {
+            pte.clear_flag(Flag::NoExecute);
+        } else {
+            pte.set_flag(Flag::NoExecute);
+        }
+        if !prot.write {
+            pte.clear_flag(Flag::Writable);
+            pte.clear_flag(Flag::CopyOnWrite);
+            return;
+        }
+        if pte.get_flag(Flag::Writable) || pte.get_flag(Flag::CopyOnWrite) {
+            return;
+        }
+        let shared = match self.region(va) {
+            Some(region) => region.sharing == Sharing::Shared,
+            None => false
+        };
+        let private = pte.get_flag(Flag::Present) && !pte.get_flag(Flag::Zero)
+            && mem.frame(pte.get_ppn() as u32).ref_count() == 1;
+        if shared || private {
+            pte.set_flag(Flag::Writable);
+        } else {
+            pte.set_flag(Flag::CopyOnWrite);
+        }
+    }
+
+    // Changes what the pages in the range allow, like mprotect. Every
+    // page in it must be mapped and a large page can only be changed as
+    // a whole.
+    pub fn protect(&mut self, mem: &mut Memory, start: u64, len: u64, prot: Prot) -> Result<(), SimError> {
+        self.check_running()?;
+        let end = start.saturating_add(len);
+        let mut entries = Vec::new();
+        let mut va = start;
+        while va < end {
+            let (table, idx, pte) = match self.entry(mem, Address::Virtual(va, 0)) {
+                Some(entry) if entry.2.get() != 0 => entry,
+                _ => return Err(SimError::InvalidAddress(va))
+            };
+            let base = self.page_base(pte, Address::Virtual(va, 0));
+            let size = if pte.get_flag(Flag::PageSize) {
+                self.geo.large_page_size()
+            } else {
+                self.geo.page_size() as u64
+            };
+            if base < start || base + (size - 1) > end - 1 {
+                return Err(SimError::InvalidArgument("range splits a large page"));
+            }
+            entries.push((base, table, idx));
+            va = match base.checked_add(size) {
+                Some(next) => next,
+                None => break
+            };
         }
 
-        let pgtab = self.tables[pde.get_ppn()].borrow();
-        
-        let raw_data = pgtab.read::<u32>(ptx * 4);
-        let pte = PTE::from(raw_to_u32(raw_data));
-        match pte.get_flag(Flag::Present) {
-            true => Some(pte),
-            false => None
+        // a shared mapping of a file opened read-only now writes it back
+        if prot.write {
+            for region in &self.regions {
+                if region.sharing == Sharing::Shared && region.start < end && start < region.start + region.len {
+                    mem.file_writable(region.file)?;
+                }
+            }
+        }
+        for (va, table, idx) in entries {
+            let mut pte = mem.read_pte(table, idx);
+            self.set_prot(mem, va, &mut pte, prot);
+            mem.write_pte(table, idx, pte);
+            mem.tlb_mut().invalidate(self.pid, va);
         }
+        Ok(())
     }
 
-    pub fn write(&mut self, vaddr: Virtual, value: ValueType) {
-        if self.state != ProcessState::Running {
-            println!("ZOMBIE {}", self.pid);
+    // Maps `len` bytes of a file at `start`, taking over the caller's
+    // reference to it. Nothing is read until the pages are first touched.
+    #[allow(clippy::too_many_arguments)]
+    pub fn map_file(&mut self, mem: &mut Memory, start: u64, len: u64, file: u32, offset: u64, prot: Prot, sharing: Sharing) -> Result<(), SimError> {
+        // the file reference is ours to drop if nothing gets mapped
+        let end = self.check_running()
+            .and_then(|_| start.checked_add(len).ok_or(SimError::InvalidAddress(start)));
+        let end = match end {
+            Ok(end) => end,
+            Err(err) => {
+                mem.close_file(file);
+                return Err(err);
+            }
+        };
+        let page_size = self.geo.page_size() as u64;
+        for va in (start..end).step_by(page_size as usize) {
+            if let Some((_, _, pte)) = self.entry(mem, Address::Virtual(va, 0)) {
+                if pte.get() != 0 {
+                    mem.close_file(file);
+                    return Err(SimError::AlreadyMapped(va));
+                }
+            }
+        }
+        self.regions.push(Region { start, len, file, offset, sharing });
+        for va in (start..end).step_by(page_size as usize) {
+            let va = Address::Virtual(va, 0);
+            let placed = self.check_canonical(mem, va)
+                .and_then(|_| self.table_for(mem, va, self.geo.levels() - 1));
+            let table = match placed {
+                Ok(table) => table,
+                Err(err) => {
+                    // take back the file reference and the pages mapped so
+                    // far, none of which has been read in
+                    self.regions.pop();
+                    mem.close_file(file);
+                    self.munmap(mem, start, va.get() - start)?;
+                    return Err(err);
+                }
+            };
+            let ptx = va.get_index(&self.geo, self.geo.levels() - 1);
+            let mut pte = PTE::new(0);
+            pte.set(0, &[Flag::User, Flag::File]);
+            self.set_prot(mem, va.get(), &mut pte, prot);
+            mem.write_pte(table, ptx, pte);
+            mem.tlb_mut().invalidate(self.pid, va.get());
+            mem.record(EventKind::Map { va: va.get(), ppn: 0 });
+        }
+        Ok(())
+    }
+
+    // Writes the dirty pages of shared file mappings in the range back.
+    pub fn msync(&mut self, mem: &mut Memory, start: u64, len: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        let end = start.saturating_add(len);
+        let page_size = self.geo.page_size() as u64;
+        for region in self.regions.clone() {
+            let from = region.start.max(start);
+            let to = (region.start + region.len).min(end);
+            for va in (from..to.max(from)).step_by(page_size as usize) {
+                if let Some((_, _, pte)) = self.locate(mem, Address::Virtual(va, 0)) {
+                    if !pte.get_flag(Flag::Zero) {
+                        mem.writeback(pte.get_ppn() as u32)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Drops every page in the range after writing back dirty pages of
+    // shared file mappings, frees the page tables it leaves empty and
+    // forgets the part of any mapped file that lay in it. A large page
+    // can only be unmapped as a whole.
+    pub fn munmap(&mut self, mem: &mut Memory, start: u64, len: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        if len == 0 {
+            return Ok(());
+        }
+        let end = start.saturating_add(len);
+        for va in [start, end - 1] {
+            if let Some((_, _, pte)) = self.entry(mem, Address::Virtual(va, 0)) {
+                let base = self.page_base(pte, Address::Virtual(va, 0));
+                let size = self.geo.large_page_size();
+                if pte.get_flag(Flag::PageSize) && (base < start || base + (size - 1) > end - 1) {
+                    return Err(SimError::InvalidArgument("range splits a large page"));
+                }
+            }
+        }
+        let entries = self.entries_in(mem, start, end);
+        if entries.iter().any(|&(_, _, _, pte)| pte.get_flag(Flag::Protected)) {
+            return Err(SimError::InvalidArgument("range holds a locked page"));
+        }
+        self.msync(mem, start, len)?;
+
+        for (va, table, idx, _) in entries {
+            // writing back may have cleaned the entry since
+            let pte = mem.read_pte(table, idx);
+            self.unmap_entry(mem, va, table, idx, pte);
+        }
+        self.reclaim_tables(mem, start, end);
+        self.vars.retain(|&va| va < start || va > end - 1);
+
+        // trim the mapped files, splitting one that the range cuts in two
+        let mut regions = Vec::new();
+        for region in self.regions.drain(..) {
+            let region_end = region.start + region.len;
+            if region_end <= start || region.start >= end {
+                regions.push(region);
+                continue;
+            }
+            let mut pieces = 0;
+            if region.start < start {
+                regions.push(Region { len: start - region.start, ..region });
+                pieces += 1;
+            }
+            if region_end > end {
+                regions.push(Region {
+                    start: end,
+                    len: region_end - end,
+                    offset: region.offset + (end - region.start),
+                    ..region
+                });
+                pieces += 1;
+            }
+            match pieces {
+                0 => mem.close_file(region.file),
+                2 => mem.dup_file(region.file),
+                _ => {}
+            }
+        }
+        self.regions = regions;
+        Ok(())
+    }
+
+    // Every entry in the range that maps something, small or large, with
+    // the address it was found at.
+    fn entries_in(&self, mem: &Memory, start: u64, end: u64) -> Vec<(u64, u32, usize, PTE)> {
+        let mut entries = Vec::new();
+        let mut va = start;
+        while va < end {
+            let next = match self.walk(mem, Address::Virtual(va, 0)) {
+                (Some((table, idx, pte)), _) => {
+                    if pte.get() != 0 {
+                        entries.push((va, table, idx, pte));
+                    }
+                    let size = if pte.get_flag(Flag::PageSize) {
+                        self.geo.large_page_size()
+                    } else {
+                        self.geo.page_size() as u64
+                    };
+                    (va | (size - 1)).checked_add(1)
+                },
+                // nothing is mapped below the missing entry
+                (None, reads) if reads > 0 => (va | ((1u64 << self.geo.shift(reads - 1)) - 1)).checked_add(1),
+                (None, _) => va.checked_add(self.geo.page_size() as u64),
+            };
+            va = match next {
+                Some(next) => next,
+                None => break
+            };
+        }
+        entries
+    }
+
+    // Clears one entry, small or large, and drops its frame or swap slot.
+    fn unmap_entry(&self, mem: &mut Memory, va: u64, table: u32, idx: usize, pte: PTE) {
+        if pte.get() == 0 {
             return;
         }
-        let d = self.pgdir.borrow();
-        match self.walk(vaddr) {
-            Some(mut pte) => {
-                let va = vaddr.get();
-                if !pte.get_flag(Flag::User) {
-                    panic!("Attempt to write to kernel page in user process");
+        let ppn = pte.get_ppn() as u32;
+        if pte.get_flag(Flag::Swapped) {
+            mem.swap_free(ppn);
+        } else if pte.get_flag(Flag::Present) && !pte.get_flag(Flag::Zero) {
+            mem.untrack(ppn, table, idx);
+            if mem.frame(ppn).ref_count() > 1 {
+                mem.frame_mut(ppn).decrement_refs();
+            } else {
+                mem.kfree(ppn);
+            }
+        }
+        mem.write_pte(table, idx, PTE::new(0));
+        mem.tlb_mut().invalidate(self.pid, va);
+        mem.record(EventKind::Unmap { va: self.page_base(pte, Address::Virtual(va, 0)) });
+    }
+
+    // Frees the page tables covering the range that have no entries
+    // left, deepest first so a parent emptied on the way goes too. The
+    // directory always stays.
+    fn reclaim_tables(&mut self, mem: &mut Memory, start: u64, end: u64) {
+        let mut tables = self.tables.clone();
+        tables.sort_by_key(|t| std::cmp::Reverse(t.level));
+        for table in tables {
+            let span = 1u64 << self.geo.shift(table.level - 1);
+            if table.va > end - 1 || table.va + (span - 1) < start {
+                continue;
+            }
+            if (0..self.geo.entries()).any(|i| mem.read_pte(table.ppn, i).get() != 0) {
+                continue;
+            }
+            let mut parent = self.pgdir;
+            for level in 0..table.level - 1 {
+                parent = mem.read_pte(parent, self.geo.index(table.va, level)).get_ppn() as u32;
+            }
+            mem.write_pte(parent, self.geo.index(table.va, table.level - 1), PTE::new(0));
+            mem.kfree(table.ppn);
+            self.tables.retain(|t| t.ppn != table.ppn);
+        }
+    }
+
+    // Detaches the shared memory segment attached at `start`.
+    pub fn detach(&mut self, mem: &mut Memory, start: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        let region = self.regions.iter()
+            .find(|r| r.start == start && mem.segment_size(r.file).is_some())
+            .copied()
+            .ok_or(SimError::InvalidAddress(start))?;
+        self.munmap(mem, region.start, region.len)
+    }
+
+    // Drops the variable at `va`, and the page or large page it lies on
+    // once no other variable there is registered.
+    pub fn unregister(&mut self, mem: &mut Memory, va: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        let (start, len) = match self.span(mem, va) {
+            Some(span) if self.vars.contains(&va) => span,
+            _ => return Err(SimError::InvalidAddress(va))
+        };
+        self.vars.remove(&va);
+        if self.vars.range(start..=start + (len - 1)).next().is_some() {
+            return Ok(());
+        }
+        if let Err(err) = self.munmap(mem, start, len) {
+            self.vars.insert(va);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Notes a variable at `va` on a page that was just registered.
+    pub fn add_var(&mut self, va: u64) {
+        self.vars.insert(va);
+    }
+
+    // Registers the variable at `va` on a page another variable already
+    // registered, so the page stays until both are unregistered. A page
+    // mapped any other way is not a variable's to share.
+    pub fn share_var(&mut self, mem: &Memory, va: u64) -> Result<(), SimError> {
+        self.check_running()?;
+        let shared = match self.span(mem, va) {
+            Some((start, len)) => self.vars.range(start..=start + (len - 1)).next().is_some(),
+            None => false
+        };
+        if !shared || !self.vars.insert(va) {
+            return Err(SimError::AlreadyMapped(va));
+        }
+        Ok(())
+    }
+
+    // Where the page or large page holding `va` starts and its size, if
+    // anything maps it.
+    fn span(&self, mem: &Memory, va: u64) -> Option<(u64, u64)> {
+        let (_, _, pte) = self.entry(mem, Address::Virtual(va, 0))?;
+        if pte.get() == 0 {
+            return None;
+        }
+        let size = if pte.get_flag(Flag::PageSize) {
+            self.geo.large_page_size()
+        } else {
+            self.geo.page_size() as u64
+        };
+        Some((self.page_base(pte, Address::Virtual(va, 0)), size))
+    }
+
+    fn mapping(&self, va: Address, table: u32, index: usize) -> Mapping {
+        Mapping {
+            pid: self.pid,
+            va: va.get_address(&self.geo),
+            table,
+            index,
+        }
+    }
+
+    pub fn lock(&mut self, mem: &mut Memory, vaddr: Virtual, locked: bool) -> Result<(), SimError> {
+        self.check_running()?;
+        self.check_canonical(mem, vaddr.get())?;
+        match self.locate(mem, vaddr.get()) {
+            Some((table, ptx, mut pte)) => {
+                if locked {
+                    pte.set_flag(Flag::Protected);
+                } else {
+                    pte.clear_flag(Flag::Protected);
+                }
+                mem.write_pte(table, ptx, pte);
+                mem.tlb_mut().invalidate(self.pid, vaddr.get().get());
+                Ok(())
+            },
+            None => Err(SimError::InvalidAddress(vaddr.get().get()))
+        }
+    }
+
+    pub fn write(&mut self, mem: &mut Memory, vaddr: Virtual, value: ValueType) -> Result<(), SimError> {
+        self.store(mem, vaddr, Some(value))
+    }
+
+    // Gives a page that still reads as the zero page a frame of its own,
+    // as the first write to it would. The page is only made writable if
+    // it may be written.
+    pub fn populate(&mut self, mem: &mut Memory, vaddr: Virtual) -> Result<(), SimError> {
+        self.check_running()?;
+        let va = vaddr.get();
+        self.check_canonical(mem, va)?;
+        match self.resolve(mem, va)? {
+            Some((table, ptx, pte)) if pte.get_flag(Flag::Zero) && pte.get_flag(Flag::User)
+                && !pte.get_flag(Flag::ProtNone) => {
+                let writable = pte.get_flag(Flag::Writable) || pte.get_flag(Flag::CopyOnWrite);
+                self.zero_fill(mem, va, table, ptx, pte, writable)
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn zero_fill(&self, mem: &mut Memory, va: Address, table: u32, ptx: usize, mut pte: PTE, writable: bool) -> Result<(), SimError> {
+        // page replacement could not free a frame either
+        let ppn = self.kalloc(mem, pte).ok_or(SimError::OutOfMemory)?;
+        remap(&mut pte, ppn);
+        if !writable {
+            pte.clear_flag(Flag::Writable);
+        }
+        mem.write_pte(table, ptx, pte);
+        mem.tlb_mut().invalidate(self.pid, va.get());
+        mem.track(ppn, self.mapping(va, table, ptx));
+        mem.record(EventKind::ZeroFill { va: self.page_base(pte, va), ppn });
+
+        if self.debug >= Verbosity::Faults {
+            let pgnum = va.translate(&self.geo).get_address(&self.geo);
+            println!("PGZERO: 0x{:x}", pgnum);
+        }
+        Ok(())
+    }
+
+    pub fn access(&mut self, mem: &mut Memory, vaddr: Virtual, access: Access) -> Result<(), SimError> {
+        match access {
+            Access::Read => self.read(mem, vaddr, DataType::UnsignedInt).map(|_| ()),
+            Access::Write => self.store(mem, vaddr, None),
+            Access::Fetch => self.fetch(mem, vaddr).map(|_| ()),
+        }
+    }
+
+    // Writes `value`, or only takes the faults and dirties the page of a
+    // write when there is none.
+    fn store(&mut self, mem: &mut Memory, vaddr: Virtual, value: Option<ValueType>) -> Result<(), SimError> {
+        self.check_running()?;
+        let va = vaddr.get();
+        self.check_canonical(mem, va)?;
+        match self.resolve(mem, va)? {
+            Some((table, ptx, mut pte)) => {
+                // kernel pages, and pages that may not be written at all
+                let allowed = pte.get_flag(Flag::Writable) || pte.get_flag(Flag::CopyOnWrite);
+                if !pte.get_flag(Flag::User) || pte.get_flag(Flag::ProtNone) || !allowed {
+                    return Err(self.protection_fault(mem, va));
                 }
-                
+
                 if pte.get_flag(Flag::Zero) {
                     // lazy alloc
-                    if let Some(pg) = alloc::kalloc() {
-                        pte.set(PTE::new(pg.ppn()).get_address(), &[
-                            Flag::Present, Flag::Writable, Flag::User
-                        ]);
-
-                        let pdx = va.get_dir_index();
-                        let ptx = va.get_table_index();
-                        let raw_pd_data = d.read::<u32>(pdx * 4);
-                        let pde = PTE::from(raw_to_u32(raw_pd_data));
-                        let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-                        pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
-
-                        if self.debug {
-                            let pgnum = va.translate().get_address() & !0xFFF;
-                            println!("PGZERO: 0x{:x}", pgnum);
+                    self.zero_fill(mem, va, table, ptx, pte, true)?;
+                    self.store(mem, vaddr, value)
+                } else if pte.get_flag(Flag::Writable) {
+                    let ppn = pte.get_ppn() as u32;
+                    let (frame, offset) = self.frame_of(pte, va);
+                    let page = mem.frame_mut(frame);
+                    if let Some(value) = value {
+                        match value {
+                            ValueType::SignedInt(_) =>
+                                page.write::<isize>(offset, value.as_bytes().as_ref()),
+                            ValueType::UnsignedInt(_) =>
+                                page.write::<usize>(offset, value.as_bytes().as_ref()),
+                            ValueType::Zero =>
+                                page.write::<usize>(offset, value.as_bytes().as_ref()),
                         }
+                    }
 
-                        self.phys_pages.insert(va.get_address(), Rc::new(RefCell::new(*pg)));
+                    pte.set_flag(Flag::Dirty);
+                    pte.set_flag(Flag::Accessed);
+                    mem.write_pte(table, ptx, pte);
+                    mem.tlb_mut().insert(self.pid, va.get(), table, ptx, pte);
+                    mem.dirty(ppn);
+                    mem.touch(ppn);
+                    Ok(())
+                } else {
+                    // the page is copy-on-write
+                    let old = pte.get_ppn() as u32;
 
-                        drop(d);
-                        drop(pgtab);
+                    // check ref count
+                    if mem.frame(old).ref_count() > 1 {
+                        // there are processes still referencing this page,
+                        // keep it resident while we make our own copy
+                        mem.pin(old);
+                        let page = self.kalloc(mem, pte);
+                        mem.unpin(old);
 
-                       self.write(vaddr, value);
-                       return;
-                    } else {
-                        // eventually replace with page replacement call,
-                        // then panic only if that fails
-                        panic!("Out of memory");
-                    }
-                } else {
-                    if pte.get_flag(Flag::Writable) {
-                        if let Some(page) = self.phys_pages.get(&va.get_address()) {
-                            let mut page_ref = page.borrow_mut();
-                            match value {
-                                ValueType::SignedInt(_) =>
-                                    page_ref.write::<isize>(va.get_offset() as usize, value.as_bytes().as_ref()),
-                                ValueType::UnsignedInt(_) =>
-                                    page_ref.write::<usize>(va.get_offset() as usize, value.as_bytes().as_ref()),
-                                ValueType::Zero =>
-                                    page_ref.write::<usize>(va.get_offset() as usize, value.as_bytes().as_ref()),
+                        if let Some(ppn) = page {
+                            mem.copy_frame(ppn, old);
+                            mem.frame_mut(old).decrement_refs();
+                            mem.untrack(old, table, ptx);
+
+                            remap(&mut pte, ppn);
+                            mem.write_pte(table, ptx, pte);
+                            mem.tlb_mut().invalidate(self.pid, va.get());
+                            mem.track(ppn, self.mapping(va, table, ptx));
+                            mem.record(EventKind::CopyOnWrite {
+                                va: self.page_base(pte, va),
+                                from: old,
+                                to: ppn,
+                            });
+
+                            if self.debug >= Verbosity::Faults {
+                                let pgnum = va.translate(&self.geo).get_address(&self.geo);
+                                println!("PGCOPY: 0x{:x}", pgnum);
                             }
-                            let pdx = va.get_dir_index();
-                            let ptx = va.get_table_index();
-                            let raw_pd_data = d.read::<u32>(pdx * 4);
-                            let pde = PTE::from(raw_to_u32(raw_pd_data));
-                            let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-
-                            pte.set_flag(Flag::Dirty);
-                            pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
-
-                            drop(pgtab);
-                            drop(d);
-                            return;
+
+                            self.store(mem, vaddr, value)
+                        } else {
+                            // page replacement could not free a frame either
+                            Err(SimError::OutOfMemory)
                         }
                     } else {
-                        // attempting to write to non-writable page
-                        // we can assume this means that the page is a copy or copied for CoW
-
-                        // obtain the page
-                        if let Some(old_page) = self.phys_pages.get_mut(&va.get_address()) {
-                            let mut old_pg = old_page.borrow_mut();
-
-                            // check ref count
-                            if old_pg.ref_count() > 1 {
-                                // there are processes still referencing this page
-                                old_pg.decrement_refs();
-                                if let Some(pg) = alloc::kalloc() {
-                                    pg.copy(&*old_pg);
-                                    drop(old_pg);
-                                    
-                                    pte.set(PTE::new(pg.ppn()).get_address(), &[
-                                        Flag::Present, Flag::Writable, Flag::User
-                                    ]);
-            
-                                    let pdx = va.get_dir_index();
-                                    let ptx = va.get_table_index();
-                                    let raw_pd_data = d.read::<u32>(pdx * 4);
-                                    let pde = PTE::from(raw_to_u32(raw_pd_data));
-                                    {
-                                        let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-                                        pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
-                                    }
-            
-                                    if self.debug {
-                                        let pgnum = va.translate().get_address() & !0xFFF;
-                                        println!("PGCOPY: 0x{:x}", pgnum);
-                                    }
-            
-                                    self.phys_pages.insert(va.get_address(), Rc::new(RefCell::new(*pg)));
-                                    
-                                    drop(d);
-                                    self.write(vaddr, value);
-                                    return;
-                                }
-                            } else {
-                                // there are no other processes referencing this page,
-                                // so simply mark it as writable and retry
-                                drop(old_pg);
-                                let pdx = va.get_dir_index();
-                                let ptx = va.get_table_index();
-                                let raw_pd_data = d.read::<u32>(pdx * 4);
-                                let pde = PTE::from(raw_to_u32(raw_pd_data));
-                                {
-                                    drop(d);
-                                    let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-
-                                    pte.set_flag(Flag::Writable);
-                                    pte.clear_flag(Flag::Dirty);
-                                    pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
-                                }
-                                self.write(vaddr, value);
-                                return;
-                            }
-                        }
+                        // there are no other processes referencing this page,
+                        // so simply mark it as writable and retry
+                        pte.set_flag(Flag::Writable);
+                        pte.clear_flag(Flag::CopyOnWrite);
+                        pte.clear_flag(Flag::Dirty);
+                        mem.write_pte(table, ptx, pte);
+                        mem.tlb_mut().invalidate(self.pid, va.get());
+                        mem.record(EventKind::Upgrade { va: self.page_base(pte, va), ppn: old });
+                        self.store(mem, vaddr, value)
                     }
                 }
             },
-            None => println!("Invalid address 0x{:x}", vaddr.get().get_address())
+            None => {
+                mem.record(EventKind::InvalidAccess { va: va.get_address(&self.geo) });
+                Err(SimError::InvalidAddress(va.get()))
+            }
         }
     }
 
-    pub fn read(&self, vaddr: Virtual, data_type: DataType) -> Option<ValueType> {
-        if self.state != ProcessState::Running {
-            println!("ZOMBIE {}", self.pid);
-            return None;
-        }
-        match self.walk(vaddr) {
-            Some(pte) => {
-                let va = vaddr.get();
+    pub fn read(&self, mem: &mut Memory, vaddr: Virtual, data_type: DataType) -> Result<ValueType, SimError> {
+        self.load(mem, vaddr, data_type, Access::Read)
+    }
+
+    pub fn fetch(&self, mem: &mut Memory, vaddr: Virtual) -> Result<ValueType, SimError> {
+        self.load(mem, vaddr, DataType::UnsignedInt, Access::Fetch)
+    }
+
+    fn load(&self, mem: &mut Memory, vaddr: Virtual, data_type: DataType, access: Access) -> Result<ValueType, SimError> {
+        self.check_running()?;
+        let va = vaddr.get();
+        self.check_canonical(mem, va)?;
+        match self.resolve(mem, va)? {
+            Some((table, ptx, mut pte)) => {
+                let no_exec = access == Access::Fetch && pte.get_flag(Flag::NoExecute);
+                if pte.get_flag(Flag::ProtNone) || no_exec {
+                    return Err(self.protection_fault(mem, va));
+                }
                 if pte.get_flag(Flag::Zero) {
-                    return Some(ValueType::UnsignedInt(0));
+                    return Ok(ValueType::UnsignedInt(0));
                 }
-                if let Some(page) = self.phys_pages.get(&va.get_address()) {
-                    let my_page = page.borrow();
-                    let val = match data_type {
-                        DataType::SignedInt => {
-                            let data = my_page.read::<isize>(va.get_offset() as usize);
-                            let mut num = 0;
-                            let mut shif = 0;
-                            for &byte in data {
-                                num |= (byte as usize) << shif;
-                                shif += 8;
-                            }
-                            ValueType::SignedInt(num as isize)
+                let ppn = pte.get_ppn() as u32;
+                pte.set_flag(Flag::Accessed);
+                mem.write_pte(table, ptx, pte);
+                mem.tlb_mut().insert(self.pid, va.get(), table, ptx, pte);
+                mem.touch(ppn);
+
+                let (frame, offset) = self.frame_of(pte, va);
+                let page = mem.frame(frame);
+                let val = match data_type {
+                    DataType::SignedInt => {
+                        let data = page.read::<isize>(offset);
+                        let mut num = 0;
+                        let mut shif = 0;
+                        for &byte in data {
+                            num |= (byte as usize) << shif;
+                            shif += 8;
                         }
-                        DataType::UnsignedInt => {
-                            let data = my_page.read::<usize>(va.get_offset() as usize);
-                            let mut num = 0;
-                            let mut shif = 0;
-                            for &byte in data {
-                                num |= (byte as usize) << shif;
-                                shif += 8;
-                            }
-                            ValueType::UnsignedInt(num as usize)
+                        ValueType::SignedInt(num as isize)
+                    }
+                    DataType::UnsignedInt => {
+                        let data = page.read::<usize>(offset);
+                        let mut num = 0;
+                        let mut shif = 0;
+                        for &byte in data {
+                            num |= (byte as usize) << shif;
+                            shif += 8;
                         }
-                    };
-                    return Some(val);
-                }
+                        ValueType::UnsignedInt(num)
+                    }
+                };
+                Ok(val)
             },
-            None => println!("Invalid address 0x{:x}", vaddr.get().get_address())
+            None => {
+                mem.record(EventKind::InvalidAccess { va: va.get_address(&self.geo) });
+                Err(SimError::InvalidAddress(va.get()))
+            }
         }
-        None
     }
 
-    pub fn copy(&mut self, child_pid: u32, debug: bool) -> Self {
-        let mut pages = HashMap::new();
-        for (&key, page) in self.phys_pages.iter_mut() {
-            let mut pg = page.borrow_mut();
-            let va = Address::Virtual(key, 0);
+    pub fn copy(&mut self, mem: &mut Memory, child_pid: u32, debug: Verbosity) -> Result<Self, SimError> {
+        self.check_running()?;
+        // allocate everything up front, since page replacement may
+        // still rewrite our own entries while it makes room
+        let pgdir = mem.kalloc().ok_or(SimError::OutOfMemory)?;
+        let mut tables: Vec<Table> = Vec::new();
+        for table in &self.tables {
+            match mem.kalloc() {
+                Some(ppn) => tables.push(Table { ppn, ..*table }),
+                None => {
+                    // give back what we took so the parent is left untouched
+                    mem.kfree(pgdir);
+                    for tb in &tables {
+                        mem.kfree(tb.ppn);
+                    }
+                    return Err(SimError::OutOfMemory);
+                }
+            };
+        }
 
-            // page walk to ensure proper mapping
-            let pdx = va.get_dir_index();
-            let ptx = va.get_table_index();
-            
-            let d = self.pgdir.borrow();
-            let raw_pd_data = d.read::<u32>(pdx * 4);
-            let pde = PTE::from(raw_to_u32(raw_pd_data));
-            if !pde.get_flag(Flag::Present) {
-                panic!("PTE does not exist");
-            }
+        // our tables and the child's, level by level
+        let mut pairs = vec![(Table { ppn: self.pgdir, level: 0, va: 0 }, pgdir)];
+        pairs.extend(self.tables.iter().zip(&tables).map(|(ours, theirs)| (*ours, theirs.ppn)));
+        let child_of = |ppn: u32| pairs.iter()
+            .find(|(ours, _)| ours.ppn == ppn)
+            .map(|&(_, theirs)| theirs)
+            .expect("PDE does not reference a page table");
 
-            let mut pgtab = self.tables[pde.get_ppn()].borrow_mut();
-            let raw_data = pgtab.read::<u32>(ptx * 4);
-            let mut pte = PTE::from(raw_to_u32(raw_data));
-            if !pte.get_flag(Flag::Present) {
-                panic!("Page not present");
-            }
+        let last = self.geo.levels() - 1;
+        for &(table, child_table) in &pairs {
+            for idx in 0..self.geo.entries() {
+                let mut pte = mem.read_pte(table.ppn, idx);
+                if table.level < last && !pte.get_flag(Flag::PageSize) {
+                    // point the child's entry at its own copy of the table below
+                    if pte.get_flag(Flag::Present) {
+                        pte.set_ppn(child_of(pte.get_ppn() as u32));
+                    }
+                    mem.write_pte(child_table, idx, pte);
+                    continue;
+                }
 
-            if pte.get_flag(Flag::Zero) {
-                pages.insert(0, Rc::new(RefCell::new(*alloc::zero_page())));
-            } else {
-                // increase ref count
-                pg.increment_refs();
+                // share every user page, small or large, read-only
+                if pte.get_flag(Flag::Present) && !pte.get_flag(Flag::Zero) {
+                    let ppn = pte.get_ppn() as u32;
 
-                // no longer writable
-                pte.clear_flag(Flag::Writable);
-                pgtab.write::<u32>(ptx * 4, u32_to_raw(pte.get()).as_ref());
-                
-                pages.insert(va.get_address(), Rc::new(RefCell::new(*pg)));
+                    // increase ref count
+                    mem.frame_mut(ppn).increment_refs();
+
+                    // copied on the next write, unless both processes share
+                    // the page through a file mapping
+                    let va = table.va | (idx as u64) << self.geo.shift(table.level);
+                    let shared = match self.region(va) {
+                        Some(region) => region.sharing == Sharing::Shared,
+                        None => false
+                    };
+                    if !shared && pte.get_flag(Flag::Writable) {
+                        pte.clear_flag(Flag::Writable);
+                        pte.set_flag(Flag::CopyOnWrite);
+                        mem.write_pte(table.ppn, idx, pte);
+                    }
+
+                    mem.track(ppn, Mapping {
+                        pid: child_pid,
+                        va,
+                        table: child_table,
+                        index: idx,
+                    });
+                } else if pte.get_flag(Flag::Swapped) {
+                    // both processes now refer to the slot
+                    mem.swap_dup(pte.get_ppn() as u32);
+                    if pte.get_flag(Flag::Writable) {
+                        pte.clear_flag(Flag::Writable);
+                        pte.set_flag(Flag::CopyOnWrite);
+                        mem.write_pte(table.ppn, idx, pte);
+                    }
+                }
+                mem.write_pte(child_table, idx, pte);
             }
         }
 
-        // copy tables
-        let mut tables: Vec<Rc<RefCell<Page>>> = Vec::new();
-        for table in self.tables.iter_mut() {
-            let page = match alloc::kalloc() {
-                Some(tb) => tb,
-                None => panic!("Out of memory")
-            };
-            page.copy(&*table.borrow());
-            tables.push(Rc::new(RefCell::new(*page)));
+        // our pages just lost write permission
+        mem.tlb_mut().flush_asid(self.pid);
+        for region in &self.regions {
+            mem.dup_file(region.file);
         }
 
-        // copy pgdir
-        let pgdir = match alloc::kalloc() {
-            Some(dir) => dir,
-            None => panic!("Out of memory")
-        };
-        pgdir.copy(&*self.pgdir.borrow());
-
         self.yieldk();
-        Self {
+        Ok(Self {
             pid: child_pid,
+            parent: Some(self.pid),
             state: ProcessState::Sleeping,
-            pgdir: Rc::new(RefCell::new(*pgdir)),
+            status: None,
+            heap_start: self.heap_start,
+            brk: self.brk,
+            pgdir,
             tables,
-            phys_pages: pages,
+            regions: self.regions.clone(),
+            vars: self.vars.clone(),
+            geo: self.geo,
             debug,
+            ws: self.ws.fresh(),
+            pff: self.pff.as_ref().map(Pff::fresh),
+        })
+    }
+
+    // This process's counters plus a snapshot of the frames it maps.
+    pub fn stats(&self, mem: &Memory) -> Stats {
+        let mut stats = mem.log().totals_for(self.pid);
+        stats.page_size = self.geo.page_size();
+        stats.total_frames = mem.nframes() as usize;
+        stats.free_frames = mem.free_frames();
+        stats.tlb = mem.tlb().stats_for(self.pid);
+        stats.walks = mem.walks_for(self.pid);
+        stats.large_page_size = self.geo.large_page_size() as usize;
+        stats.tlb_reach = mem.tlb().reach_for(self.pid) as usize;
+        // a zombie's tables are gone, only what it did is left
+        if self.is_zombie() {
+            return stats;
         }
+        stats.table_frames = 1 + self.tables.len();
+        // a frame mapped at several addresses is still one frame
+        let mut frames = HashSet::new();
+        for (_, _, pde) in self.large_pages(mem) {
+            stats.large_pages += 1;
+            if pde.get_flag(Flag::Zero) {
+                continue;
+            }
+            let n = self.geo.large_page_frames();
+            stats.resident_pages += n;
+            let ppn = pde.get_ppn() as u32;
+            if !frames.insert(ppn) {
+                continue;
+            }
+            stats.data_frames += n;
+            if mem.shared(ppn) {
+                stats.shared_frames += n;
+            } else {
+                stats.private_frames += n;
+            }
+        }
+        for table in self.tables_at(self.geo.levels() - 1) {
+            for ptx in 0..self.geo.entries() {
+                let pte = mem.read_pte(table, ptx);
+                if pte.get_flag(Flag::Swapped) {
+                    stats.swapped_pages += 1;
+                } else if pte.get_flag(Flag::Present) && !pte.get_flag(Flag::Zero) {
+                    stats.resident_pages += 1;
+                    let ppn = pte.get_ppn() as u32;
+                    if !frames.insert(ppn) {
+                        continue;
+                    }
+                    stats.data_frames += 1;
+                    if mem.shared(ppn) {
+                        stats.shared_frames += 1;
+                    } else {
+                        stats.private_frames += 1;
+                    }
+                }
+            }
+        }
+        stats
     }
 
-    pub fn print_mem(&self) {
-        if self.debug {
+    pub fn print_mem(&self, mem: &Memory) {
+        if let Some(status) = self.status {
+            println!("ZOMBIE, EXIT STATUS {}", status);
+            return;
+        }
+        if self.debug >= Verbosity::Full {
             println!("PAGE DIRECTORY\n");
-            for i in 0..1024 {
-                let d = self.pgdir.borrow();
-                let raw_pd_data = d.read::<u32>(i * 4);
-                let entry = PTE::from(raw_to_u32(raw_pd_data));
+            for i in 0..self.geo.entries() {
+                let entry = mem.read_pte(self.pgdir, i);
                 println!("PDE #{}\t PTN: {}, Flags: 0x{:x}", i, entry.get_ppn(), entry.get() & 0xFFF);
             }
             println!();
-            for i in 0..self.tables.len() {
+            let mut pages: Vec<u32> = Vec::new();
+            let last = self.geo.levels() - 1;
+            for (i, table) in self.tables.iter().enumerate() {
                 println!("PAGE TABLE #{}\n", i);
-                for j in 0..1024 {
-                    let table = self.tables[i].borrow();
-                    let entry = PTE::from(raw_to_u32(table.read::<u32>(j * 4)));
+                for j in 0..self.geo.entries() {
+                    let entry = mem.read_pte(table.ppn, j);
                     println!("PTE #{}\t PPN: {}, Flags: 0x{:x}", j, entry.get_ppn(), entry.get() & 0xFFF);
+                    if table.level == last && entry.get_flag(Flag::Present) && !entry.get_flag(Flag::Zero) {
+                        pages.push(entry.get_ppn() as u32);
+                    }
                 }
                 println!();
             }
             println!();
-            for (_, page) in self.phys_pages.iter() {
-                let pg = page.borrow();
-                println!("PAGE #{}\n", pg.ppn());
-                for i in 0..1024 {
+            for ppn in pages {
+                let pg = mem.frame(ppn);
+                println!("PAGE #{}\n", ppn);
+                for i in 0..self.geo.page_size() / 4 {
                     let word = raw_to_u32(pg.read::<u32>(i * 4));
                     println!("Word #{}: 0x{:x}", i, word);
                 }
@@ -425,24 +1390,25 @@ impl Process {
     }
 }
 
+// Points `pte` at a freshly allocated private frame.
+fn remap(pte: &mut PTE, ppn: u32) {
+    let old = *pte;
+    pte.set(PTE::new(ppn).get_address(), &[
+        Flag::Present, Flag::Writable, Flag::User
+    ]);
+    for flag in [Flag::Protected, Flag::PageSize, Flag::NoExecute] {
+        if old.get_flag(flag) {
+            pte.set_flag(flag);
+        }
+    }
+}
+
 fn raw_to_u32(raw_data: &[u8]) -> u32 {
     let mut shif = 0;
     let mut val: u32 = 0;
-    for i in 0..raw_data.len() {
-        val |= (raw_data[i] as u32) << shif;
+    for &byte in raw_data {
+        val |= (byte as u32) << shif;
         shif += 8;
     }
     val
 }
-
-fn u32_to_raw(data: u32) -> Box<[u8]> {
-    let mut v = Box::new(Vec::<u8>::new());
-
-    let mut shif = 0;
-    for _ in 0..4 {
-        v.push(((data >> shif) & 0xFF) as u8);
-        shif += 8;
-    }
-
-    v.into_boxed_slice()
-}
\ No newline at end of file
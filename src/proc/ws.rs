@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+// Denning's working set: the pages a process referenced within its last
+// `window` references, counted in its own virtual time. Every `interval`
+// references the pages whose accessed bits were set since the sample
+// before are noted as used then, so the set is only as exact as the
+// sampling.
+#[derive(Clone, Debug, Default)]
+pub struct WorkingSet {
+    window: u64,
+    interval: u64,
+    // references the process has made
+    vtime: u64,
+    // when each page was last seen referenced
+    last_use: HashMap<u64, u64>,
+}
+
+impl WorkingSet {
+    // An interval of zero never samples, leaving the set empty.
+    pub fn new(window: u64, interval: u64) -> Self {
+        Self {
+            window,
+            interval,
+            ..Self::default()
+        }
+    }
+
+    // An empty set with the same window, for a forked child.
+    pub fn fresh(&self) -> Self {
+        Self::new(self.window, self.interval)
+    }
+
+    pub fn vtime(&self) -> u64 {
+        self.vtime
+    }
+
+    // Counts a reference and says whether a sample is due.
+    pub fn tick(&mut self) -> bool {
+        self.vtime += 1;
+        self.interval > 0 && self.vtime.is_multiple_of(self.interval)
+    }
+
+    // Notes the pages referenced since the last sample and forgets the
+    // ones that have gone a whole window without one.
+    pub fn sample<I: IntoIterator<Item = u64>>(&mut self, referenced: I) {
+        for va in referenced {
+            self.last_use.insert(va, self.vtime);
+        }
+        let (now, window) = (self.vtime, self.window);
+        self.last_use.retain(|_, &mut used| now - used < window);
+    }
+
+    pub fn size(&self) -> usize {
+        self.last_use.len()
+    }
+
+    pub fn contains(&self, va: u64) -> bool {
+        self.last_use.contains_key(&va)
+    }
+}
+
+// Page-fault-frequency control of how many frames a process may keep
+// resident. At every sample the faults per thousand references since the
+// one before are compared against the bounds: above `high` the process is
+// given a quarter more frames, below `low` a quarter fewer, though never
+// fewer than its working set holds.
+#[derive(Clone, Debug)]
+pub struct Pff {
+    low: u64,
+    high: u64,
+    refs: u64,
+    faults: u64,
+    limit: Option<usize>,
+}
+
+impl Pff {
+    pub fn new(low: u64, high: u64) -> Self {
+        Self {
+            low,
+            high,
+            refs: 0,
+            faults: 0,
+            limit: None,
+        }
+    }
+
+    // The same bounds with no allocation yet, for a forked child.
+    pub fn fresh(&self) -> Self {
+        Self::new(self.low, self.high)
+    }
+
+    pub fn count(&mut self, faulted: bool) {
+        self.refs += 1;
+        if faulted {
+            self.faults += 1;
+        }
+    }
+
+    // Frames the process may keep, none until the first adjustment.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    // Picks the allocation for the next interval, starting from what the
+    // process has resident the first time, and never above `max`.
+    pub fn adjust(&mut self, resident: usize, working_set: usize, max: usize) -> usize {
+        let limit = self.limit.unwrap_or(resident).max(1);
+        let step = (limit / 4).max(1);
+        let rate = self.faults * 1000 / self.refs.max(1);
+        let limit = if rate > self.high {
+            (limit + step).min(max)
+        } else if rate < self.low {
+            limit.saturating_sub(step).max(working_set).max(1)
+        } else {
+            limit
+        };
+        self.refs = 0;
+        self.faults = 0;
+        self.limit = Some(limit);
+        limit
+    }
+}
@@ -1,89 +1,655 @@
 #![allow(dead_code, unused)]
 
 use crate::mem::ptable::{Flag, Virtual, Physical};
+use crate::proc::image::{Image, WORD};
 use crate::proc::proc::{Process};
-use crate::mem::alloc::{self, Page};
+use crate::proc::ws::{Pff, WorkingSet};
+use crate::mem::alloc::{Memory, Page};
+use crate::mem::mmap::{Prot, Sharing};
+use crate::mem::replace::ReplacementPolicy;
+use super::config::{SimulatorConfig, Verbosity};
+use super::error::SimError;
+use super::event::{Event, EventKind, EventLog};
+use super::stats::{ProcessInfo, ProcessList, Stats};
+use super::machine::Machine;
 use super::pointer::Pointer;
+use super::trace::{self, Trace, TraceError, TraceFormat, TraceReport};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::vec::Vec;
 
+// The status a killed process exits with, as a shell reports SIGKILL.
+pub const KILLED: u8 = 128 + 9;
+// The status of a process whose exec failed after its old address space
+// was gone, which Linux kills with SIGSEGV.
+pub const EXEC_FAILED: u8 = 128 + 11;
+
 pub struct Simulator {
-    proc_list: Vec<Process>,
-    curr_proc: usize,
-    debug: bool,
+    // every process by pid, zombies included
+    procs: BTreeMap<u32, Process>,
+    // the pid of the running process
+    curr: u32,
+    // pids are never reused, even once a zombie is reaped
+    next_pid: u32,
+    machine: Machine,
+    config: SimulatorConfig,
+    debug: Verbosity,
 }
 
 impl Simulator {
-    pub fn begin(debug: bool) -> Self {
-        alloc::kinit();
-        let mut v = Vec::<Process>::new();
-        let mut proc = Process::new(0, debug);
+    pub fn begin(config: SimulatorConfig) -> Result<Self, SimError> {
+        let policy = config.get_policy().build(config.get_seed(), config.get_ws_window());
+        Self::start(config, policy)
+    }
+
+    pub fn with_policy<P: ReplacementPolicy + 'static>(config: SimulatorConfig, policy: P) -> Result<Self, SimError> {
+        Self::start(config, Box::new(policy))
+    }
+
+    fn start(config: SimulatorConfig, policy: Box<dyn ReplacementPolicy>) -> Result<Self, SimError> {
+        config.validate().map_err(|err| SimError::InvalidConfig(err.to_string()))?;
+        let mut machine = Machine::new(&config, policy);
+        if config.get_swap() > 0 {
+            machine.mem_mut().swapon_anonymous(config.get_swap())?;
+        }
+
+        let debug = config.get_debug();
+        let mut procs = BTreeMap::new();
+        let mut proc = Process::new(machine.mem_mut(), 0, config.geometry(), debug)?;
+        let ws = WorkingSet::new(config.get_ws_window(), config.get_ws_interval());
+        proc.watch(ws, config.get_pff().map(|(low, high)| Pff::new(low, high)));
+        proc.set_heap(config.get_heap_base());
         proc.wake_up();
-        v.push(proc);
-        Self {
-            proc_list: v,
-            curr_proc: 0,
+        procs.insert(0, proc);
+        Ok(Self {
+            procs,
+            curr: 0,
+            next_pid: 1,
+            machine,
+            config,
             debug,
+        })
+    }
+
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    // Hands out the next virtual address for a variable of type `T`.
+    pub fn pointer<T>(&mut self, ptr: &mut T) -> Pointer<T> {
+        let vaddr = self.machine.next_vaddr(std::mem::size_of::<T>());
+        Pointer::new(vaddr, ptr)
+    }
+
+    pub fn pointer_from<T>(&mut self, addr: usize) -> Pointer<T> {
+        let vaddr = self.machine.next_vaddr(std::mem::size_of::<T>());
+        Pointer::from_addr(vaddr, addr)
+    }
+
+    pub fn swapon<P: AsRef<Path>>(&mut self, path: P, slots: u32) -> io::Result<()> {
+        self.machine.mem_mut().swapon(path, slots)
+    }
+
+    // Maps the page holding `addr`, or if another variable on it was
+    // registered already, keeps the page mapped for this one too.
+    pub fn register<T>(&mut self, addr: Pointer<T>) -> Result<(), SimError> {
+        self.check_alive()?;
+        let va = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        let pg = self.machine.mem().zero_page();
+        let pa = Physical::new(0, pg as *const Page as usize);
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let mem = self.machine.mem_mut();
+        if proc.mapped(mem, va) {
+            return proc.share_var(mem, addr.vaddr());
         }
+        proc.map(mem, va, pa, &[Flag::User, Flag::Zero, Flag::CopyOnWrite, Flag::NoExecute])?;
+        proc.add_var(addr.vaddr());
+        Ok(())
+    }
+
+    // Drops the variable at `addr`. Its page goes once no other variable
+    // registered on it is left, and its frame once no other process maps
+    // it. A large page goes as a whole.
+    pub fn unregister<T>(&mut self, addr: Pointer<T>) -> Result<(), SimError> {
+        self.check_alive()?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.unregister(self.machine.mem_mut(), addr.vaddr())
+    }
+
+    // Registers the whole large page holding `addr`, e.g. 4 MiB with
+    // two-level paging, so every address in it is valid without a page
+    // table of its own. It is backed by contiguous frames on first write.
+    pub fn register_large<T>(&mut self, addr: Pointer<T>) -> Result<(), SimError> {
+        self.check_alive()?;
+        let va = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        let pg = self.machine.mem().zero_page();
+        let pa = Physical::new(0, pg as *const Page as usize);
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.map_large(self.machine.mem_mut(), va, pa, &[Flag::User, Flag::Zero, Flag::CopyOnWrite, Flag::NoExecute])?;
+        proc.add_var(addr.vaddr());
+        Ok(())
+    }
+
+    // Maps `len` bytes of the file at `path`, starting `offset` bytes in,
+    // into the current process and returns the address of the mapping.
+    // Pages are read from the file on first access.
+    pub fn mmap_file<P: AsRef<Path>>(&mut self, path: P, offset: u64, len: usize, prot: Prot, sharing: Sharing) -> Result<u64, SimError> {
+        self.check_alive()?;
+        let len = self.page_range(offset, len)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let file = self.machine.mem_mut().open_file(path, sharing == Sharing::Shared && prot.write)?;
+        let start = self.machine.next_region(len);
+        proc.map_file(self.machine.mem_mut(), start, len, file, offset, prot, sharing)?;
+        Ok(start)
+    }
+
+    // Opens the shared memory segment called `name`, creating it with
+    // `len` bytes of zeros if it does not exist, and returns its id. The
+    // segment lives until it has been closed and detached everywhere.
+    pub fn shm_open(&mut self, name: &str, len: usize) -> Result<u32, SimError> {
+        let len = self.page_range(0, len)?;
+        self.machine.mem_mut().open_segment(name, len)
+    }
+
+    // Attaches the whole segment to the current process at `addr`. Every
+    // process attaching it sees the same frames and each other's writes.
+    pub fn shm_attach(&mut self, id: u32, addr: u64) -> Result<(), SimError> {
+        self.check_alive()?;
+        let len = self.machine.mem().segment_size(id)
+            .ok_or(SimError::InvalidArgument("no such segment"))?;
+        self.page_range(addr, len as usize)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let mem = self.machine.mem_mut();
+        mem.dup_file(id);
+        proc.map_file(mem, addr, len, id, 0, Prot::READ_WRITE, Sharing::Shared)
+    }
+
+    pub fn shm_detach(&mut self, addr: u64) -> Result<(), SimError> {
+        self.check_alive()?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.detach(self.machine.mem_mut(), addr)
     }
 
-    pub fn register<T>(&mut self, addr: Pointer<T>) {
-        if !self.check_valid(addr) {
-            let va = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
-            let pg = alloc::zero_page();
-            let pa = Physical::new(0, pg as *const Page as usize);
-            self.proc_list[self.curr_proc].map(
-                va, pa,
-                &[Flag::User, Flag::Zero]
-            );
-        } else {
-            println!("Mapping already registered for 0x{:x}.", addr.vaddr());
+    // Drops the handle `shm_open` returned.
+    pub fn shm_close(&mut self, id: u32) -> Result<(), SimError> {
+        let mem = self.machine.mem_mut();
+        if mem.segment_size(id).is_none() {
+            return Err(SimError::InvalidArgument("no such segment"));
         }
+        mem.close_file(id);
+        Ok(())
     }
 
-    fn check_valid<T>(&self, addr: Pointer<T>) -> bool {
-        self.proc_list[self.curr_proc].mapped(
+    // Writes dirty pages of shared file mappings in the range back to their files.
+    pub fn msync(&mut self, addr: u64, len: usize) -> Result<(), SimError> {
+        self.check_alive()?;
+        let len = self.page_range(addr, len)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.msync(self.machine.mem_mut(), addr, len)
+    }
+
+    pub fn munmap(&mut self, addr: u64, len: usize) -> Result<(), SimError> {
+        self.check_alive()?;
+        let len = self.page_range(addr, len)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.munmap(self.machine.mem_mut(), addr, len)
+    }
+
+    // Changes what the pages in the range allow. Writes to a page without
+    // write permission, or any access to one with `Prot::NONE`, then fail
+    // with `ProtectionFault`.
+    pub fn protect(&mut self, addr: u64, len: usize, prot: Prot) -> Result<(), SimError> {
+        self.check_alive()?;
+        let len = self.page_range(addr, len)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.protect(self.machine.mem_mut(), addr, len, prot)
+    }
+
+    // Checks that a range starts on a page boundary and rounds its
+    // length up to whole pages.
+    fn page_range(&self, start: u64, len: usize) -> Result<u64, SimError> {
+        let page_size = self.config.get_page_size() as u64;
+        if len == 0 {
+            return Err(SimError::InvalidArgument("length is zero"));
+        }
+        if !start.is_multiple_of(page_size) {
+            return Err(SimError::InvalidArgument("not aligned to a page"));
+        }
+        Ok((len as u64).div_ceil(page_size) * page_size)
+    }
+
+    pub fn lock<T>(&mut self, addr: Pointer<T>) -> Result<(), SimError> {
+        self.set_locked(addr, true)
+    }
+
+    pub fn unlock<T>(&mut self, addr: Pointer<T>) -> Result<(), SimError> {
+        self.set_locked(addr, false)
+    }
+
+    fn set_locked<T>(&mut self, addr: Pointer<T>, locked: bool) -> Result<(), SimError> {
+        self.check_alive()?;
+        let va = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.lock(self.machine.mem_mut(), va, locked)
+    }
+
+    // Whether `addr` is mapped in the current process.
+    pub fn mapped<T>(&self, addr: Pointer<T>) -> Result<bool, SimError> {
+        self.check_alive()?;
+        let proc = self.procs.get(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        Ok(proc.mapped(
+            self.machine.mem(),
             Virtual::new(addr.vaddr(), addr.as_ptr() as usize)
-        )
+        ))
     }
 
-    pub fn write<T>(&mut self, addr: Pointer<T>, value: ValueType) {
-        let proc = &mut self.proc_list[self.curr_proc];
-        proc.write(Virtual::new(addr.vaddr(), addr.as_ptr() as usize), value);
+    pub fn write<T>(&mut self, addr: Pointer<T>, value: ValueType) -> Result<(), SimError> {
+        let vaddr = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        self.reference(|proc, mem| proc.write(mem, vaddr, value))
     }
 
-    pub fn read<T>(&self, addr: Pointer<T>, data_type: DataType) -> Option<ValueType> {
-        let proc = &self.proc_list[self.curr_proc];
-        proc.read(Virtual::new(addr.vaddr(), addr.as_ptr() as usize), data_type)
+    pub fn read<T>(&mut self, addr: Pointer<T>, data_type: DataType) -> Result<ValueType, SimError> {
+        let vaddr = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        self.reference(|proc, mem| proc.read(mem, vaddr, data_type))
     }
 
-    pub fn fork(&mut self) {
-        let size = self.proc_list.len() as u32;
-        let mut new_proc = self.proc_list[self.curr_proc].copy(size, self.debug);
+    // Fetches the instruction word at `addr`.
+    pub fn fetch<T>(&mut self, addr: Pointer<T>) -> Result<ValueType, SimError> {
+        let vaddr = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        self.reference(|proc, mem| proc.fetch(mem, vaddr))
+    }
+
+    // Touches `addr` the way a load, store or fetch would, taking the
+    // same faults, without caring about the data. A store leaves the
+    // value at `addr` as it was.
+    pub fn access<T>(&mut self, addr: Pointer<T>, access: Access) -> Result<(), SimError> {
+        let vaddr = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        self.reference(|proc, mem| proc.access(mem, vaddr, access))
+    }
+
+    // Like `access`, but a page still on the zero page is given a frame
+    // first, so reading or fetching from it faults as a write would.
+    // Trace replay uses it, since every page a traced program touched
+    // had a frame.
+    pub fn touch<T>(&mut self, addr: Pointer<T>, access: Access) -> Result<(), SimError> {
+        let vaddr = Virtual::new(addr.vaddr(), addr.as_ptr() as usize);
+        self.reference(|proc, mem| {
+            proc.populate(mem, vaddr)?;
+            proc.access(mem, vaddr, access)
+        })
+    }
+
+    // Makes one reference as the current process and counts it towards
+    // its working set, noting whether it faulted.
+    fn reference<R, F>(&mut self, make: F) -> Result<R, SimError>
+    where
+        F: FnOnce(&mut Process, &mut Memory) -> Result<R, SimError>,
+    {
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let mem = self.machine.mem_mut();
+        let before = mem.log().totals().faults.total();
+        let result = make(proc, mem);
+        let faulted = mem.log().totals().faults.total() > before;
+        proc.referenced(mem, faulted);
+        mem.referenced();
+        result
+    }
+
+    // Forks the current process and switches to the child, returning its pid.
+    pub fn fork(&mut self) -> Result<u32, SimError> {
+        self.check_alive()?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let mut new_proc = proc.copy(self.machine.mem_mut(), self.next_pid, self.debug)?;
+        self.next_pid += 1;
         new_proc.wake_up();
-        self.curr_proc = new_proc.pid() as usize;
-        self.proc_list.push(new_proc);
+        let pid = new_proc.pid();
+        let mem = self.machine.mem_mut();
+        mem.record(EventKind::Fork { child: pid });
+        mem.set_pid(pid);
+        mem.tlb_mut().context_switch();
+        self.curr = pid;
+        self.procs.insert(pid, new_proc);
+        Ok(pid)
+    }
+
+    // Kills the current process, which exits with `KILLED`. Pid 0 can be
+    // killed too, which leaves nothing running until another process is
+    // switched to.
+    pub fn kill(&mut self) -> Result<(), SimError> {
+        self.check_alive()?;
+        self.machine.mem_mut().record(EventKind::Kill);
+        self.terminate(KILLED)
+    }
+
+    // Exits the current process with `status`. Its memory is freed at once
+    // but it stays behind as a zombie until its parent waits for it, and
+    // its children are handed to pid 0. The parent runs next.
+    pub fn exit(&mut self, status: u8) -> Result<(), SimError> {
+        self.check_can_exit()?;
+        self.check_alive()?;
+        self.machine.mem_mut().record(EventKind::Exit { status });
+        self.terminate(status)
+    }
+
+    // Pid 0 is the one every orphan ends up with, so it cannot go.
+    fn check_can_exit(&self) -> Result<(), SimError> {
+        match self.curr {
+            0 => Err(SimError::InvalidArgument("pid 0 cannot exit")),
+            _ => Ok(())
+        }
+    }
+
+    // A process killed with no parent to go back to, like pid 0, stays
+    // current, but has nothing left to map, fork or look up.
+    fn check_alive(&self) -> Result<(), SimError> {
+        match self.procs.get(&self.curr) {
+            Some(proc) if proc.is_zombie() => Err(SimError::NotRunning(self.curr)),
+            Some(_) => Ok(()),
+            None => Err(SimError::NoSuchProcess(self.curr))
+        }
+    }
+
+    fn terminate(&mut self, status: u8) -> Result<(), SimError> {
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.exit(self.machine.mem_mut(), status);
+        let (pid, parent) = (proc.pid(), proc.parent());
+        for child in self.procs.values_mut().filter(|p| p.parent() == Some(pid)) {
+            child.reparent(0);
+        }
+        match parent {
+            Some(parent) if self.procs.get(&parent).is_some_and(|p| !p.is_zombie()) => self.switch(parent),
+            // pid 0 was killed, so there is nothing to go back to
+            _ => Ok(())
+        }
+    }
+
+    // Reaps a child of the current process that has exited, returning its
+    // pid and exit status. Gives none while every child is still running,
+    // as there is nothing to block on until one of them is switched to.
+    pub fn wait(&mut self) -> Result<Option<(u32, u8)>, SimError> {
+        let pid = self.curr;
+        let mut children = self.procs.values().filter(|p| p.parent() == Some(pid)).peekable();
+        if children.peek().is_none() {
+            return Err(SimError::NoChildren(pid));
+        }
+        let child = match children.find(|p| p.is_zombie()) {
+            Some(child) => child.pid(),
+            None => return Ok(None)
+        };
+        let status = self.procs.remove(&child).and_then(|p| p.status()).unwrap_or(0);
+        self.machine.mem_mut().record(EventKind::Wait { child, status });
+        Ok(Some((child, status)))
+    }
+
+    // Replaces the current process's address space with `image`, as exec
+    // does, and returns where its stack pointer starts if it has a stack.
+    // The image is checked before the old address space is torn down, and
+    // if loading it fails after that, e.g. running out of memory, there is
+    // nothing to go back to, so the process exits with `EXEC_FAILED`.
+    pub fn exec(&mut self, image: &Image) -> Result<Option<u64>, SimError> {
+        image.validate(self.config.get_page_size() as u64)?;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.exec(self.machine.mem_mut())?;
+        // the heap follows the last segment, as it would the bss
+        let page_size = self.config.get_page_size() as u64;
+        let heap = image.segments().iter()
+            .map(|seg| (seg.start + seg.len).div_ceil(page_size) * page_size)
+            .max()
+            .unwrap_or(self.config.get_heap_base());
+        proc.set_heap(heap);
+        self.machine.mem_mut().record(EventKind::Exec);
+        match self.load_image(image) {
+            Ok(sp) => Ok(sp),
+            Err(err) => {
+                self.machine.mem_mut().record(EventKind::Exit { status: EXEC_FAILED });
+                self.terminate(EXEC_FAILED)?;
+                Err(err)
+            }
+        }
+    }
+
+    fn load_image(&mut self, image: &Image) -> Result<Option<u64>, SimError> {
+        for seg in image.segments() {
+            self.load(seg.start, seg.len, seg.start, &seg.data, seg.prot)?;
+        }
+        let (start, size) = match image.stack_range() {
+            Some(range) => range,
+            None => return Ok(None)
+        };
+        let (sp, bytes) = image.initial_stack().unwrap_or_default();
+        self.load(start, size, sp, &bytes, Prot::READ_WRITE)?;
+        Ok(Some(sp))
     }
 
-    pub fn kill(&mut self) {
-        self.proc_list[self.curr_proc].kill();
-        self.proc_list.remove(self.curr_proc);
-        if self.curr_proc > 0 {
-            self.curr_proc -= 1;
-            self.proc_list[self.curr_proc].wake_up();
+    // Maps `len` bytes at `start` to the zero page, copies `data` in at
+    // `at` a word at a time and then gives the range `prot`. Words are
+    // 8 bytes whatever the host, and those that are all zero are left to
+    // the zero page.
+    fn load(&mut self, start: u64, len: u64, at: u64, data: &[u8], prot: Prot) -> Result<(), SimError> {
+        let page_size = self.config.get_page_size() as u64;
+        let len = len.div_ceil(page_size) * page_size;
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let mem = self.machine.mem_mut();
+        proc.map_zero(mem, start, len)?;
+        for (i, chunk) in data.chunks(WORD as usize).enumerate() {
+            let mut word = [0; WORD as usize];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let value = u64::from_le_bytes(word);
+            if value != 0 {
+                let va = at + i as u64 * WORD;
+                let value = usize::try_from(value)
+                    .map_err(|_| SimError::InvalidArgument("word does not fit in a usize"))?;
+                proc.write(mem, Virtual::new(va, 0), ValueType::UnsignedInt(value))?;
+            }
+        }
+        proc.protect(mem, start, len, prot)
+    }
+
+    // Moves the program break of the current process by `delta` bytes,
+    // like sbrk, and returns where it was. The heap grows lazily, onto
+    // the zero page, and frees its frames when it shrinks.
+    pub fn sbrk(&mut self, delta: i64) -> Result<u64, SimError> {
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        let (_, old) = proc.heap();
+        let new = old.checked_add_signed(delta)
+            .ok_or(SimError::InvalidArgument("break out of range"))?;
+        proc.brk(self.machine.mem_mut(), new)?;
+        Ok(old)
+    }
+
+    // Sets the program break of the current process to `addr`, like brk.
+    pub fn brk(&mut self, addr: u64) -> Result<(), SimError> {
+        let proc = self.procs.get_mut(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        proc.brk(self.machine.mem_mut(), addr)
+    }
+
+    // Where the heap of the current process starts, and its break.
+    pub fn heap(&self) -> Result<(u64, u64), SimError> {
+        let proc = self.procs.get(&self.curr)
+            .ok_or(SimError::NoSuchProcess(self.curr))?;
+        Ok(proc.heap())
+    }
+
+    // The pid of the current process's parent, none for pid 0.
+    pub fn ppid(&self) -> Option<u32> {
+        self.procs.get(&self.curr).and_then(|proc| proc.parent())
+    }
+
+    pub fn switch(&mut self, pid: u32) -> Result<(), SimError> {
+        let proc = self.procs.get(&pid).ok_or(SimError::NoSuchProcess(pid))?;
+        if proc.is_zombie() {
+            return Err(SimError::NotRunning(pid));
         }
+        if let Some(proc) = self.procs.get_mut(&self.curr) {
+            proc.yieldk();
+        }
+        self.curr = pid;
+        if let Some(proc) = self.procs.get_mut(&pid) {
+            proc.wake_up();
+        }
+        let mem = self.machine.mem_mut();
+        mem.record(EventKind::Switch { to: pid });
+        mem.set_pid(pid);
+        mem.tlb_mut().context_switch();
+        Ok(())
+    }
+
+    // The pid of the current process.
+    pub fn current(&self) -> u32 {
+        self.curr
     }
 
-    pub fn switch(&mut self, proc_num: usize) {
-        self.proc_list[self.curr_proc].yieldk();
-        self.curr_proc = proc_num;
-        self.proc_list[self.curr_proc].wake_up();
+    // Every process in pid order, with its state, parent and resident
+    // pages, as `ps` lists them.
+    pub fn ps(&self) -> ProcessList {
+        let mem = self.machine.mem();
+        let procs = self.procs.values()
+            .map(|proc| ProcessInfo {
+                pid: proc.pid(),
+                parent: proc.parent(),
+                state: proc.state(),
+                resident: proc.stats(mem).resident_pages,
+            })
+            .collect();
+        ProcessList { procs }
+    }
+
+    // Counters for the whole machine and a snapshot of its frames.
+    pub fn stats(&self) -> Stats {
+        let mem = self.machine.mem();
+        let mut stats = mem.log().totals();
+        stats.page_size = mem.page_size();
+        stats.total_frames = mem.nframes() as usize;
+        stats.free_frames = mem.free_frames();
+        stats.tlb = mem.tlb().stats();
+        stats.tlb_reach = mem.tlb().reach() as usize;
+        stats.walks = mem.walks();
+        stats.large_page_size = self.config.geometry().large_page_size() as usize;
+        for proc in self.procs.values() {
+            let ps = proc.stats(mem);
+            stats.table_frames += ps.table_frames;
+            stats.resident_pages += ps.resident_pages;
+            stats.swapped_pages += ps.swapped_pages;
+            stats.large_pages += ps.large_pages;
+        }
+        stats.cached_frames = mem.cached_frames();
+        stats.shmem_frames = mem.segment_frames();
+        for ppn in mem.resident_frames() {
+            stats.data_frames += 1;
+            if mem.shared(ppn) {
+                stats.shared_frames += 1;
+            } else {
+                stats.private_frames += 1;
+            }
+        }
+        for ppn in mem.large_pages() {
+            let frames = mem.large_page_frames();
+            stats.data_frames += frames;
+            if mem.shared(ppn) {
+                stats.shared_frames += frames;
+            } else {
+                stats.private_frames += frames;
+            }
+        }
+        stats
+    }
+
+    // Stats for the process with `pid`.
+    pub fn process_stats(&self, pid: u32) -> Result<Stats, SimError> {
+        match self.procs.get(&pid) {
+            Some(proc) => Ok(proc.stats(self.machine.mem())),
+            None => Err(SimError::NoSuchProcess(pid))
+        }
+    }
+
+    // The working set of the process with `pid` as of its last sample, in pages.
+    pub fn working_set(&self, pid: u32) -> Result<usize, SimError> {
+        match self.procs.get(&pid) {
+            Some(proc) => Ok(proc.working_set().size()),
+            None => Err(SimError::NoSuchProcess(pid))
+        }
+    }
+
+    // How the working set of `pid` changed, as the number of references
+    // it had made at every sample and the pages in the set then. Nothing
+    // is sampled unless the config sets ws_interval.
+    pub fn working_set_history(&self, pid: u32) -> Vec<(u64, u64)> {
+        self.events()
+            .filter(|event| event.pid == pid)
+            .filter_map(|event| match event.kind {
+                EventKind::WorkingSet { refs, pages, .. } => Some((refs, pages)),
+                _ => None
+            })
+            .collect()
+    }
+
+    pub fn meminfo(&self) -> String {
+        self.stats().to_string()
+    }
+
+    // Replays the memory reference trace at `path`, see `trace::replay`.
+    pub fn replay<P: AsRef<Path>>(&mut self, path: P, format: Option<TraceFormat>) -> Result<TraceReport, TraceError> {
+        let trace = Trace::open(path, format)?;
+        trace::replay(self, &trace)
+    }
+
+    // Everything the kernel has done so far, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.machine.mem().log().iter()
+    }
+
+    pub fn event_log(&self) -> &EventLog {
+        self.machine.mem().log()
+    }
+
+    pub fn export_events<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.event_log().write_jsonl(&mut out)?;
+        out.flush()
+    }
+
+    pub fn export_working_sets<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.event_log().write_working_sets(&mut out)?;
+        out.flush()
+    }
+
+    pub fn export_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.event_log().write_chrome_trace(&mut out)?;
+        out.flush()
     }
 
     pub fn print(&self) {
-        if self.debug {
-            for proc in self.proc_list.iter() {
+        if self.debug >= Verbosity::Full {
+            for proc in self.procs.values() {
                 println!("PROCESS PID {}\n", proc.pid());
-                proc.print_mem();
+                proc.print_mem(self.machine.mem());
                 println!();
             }
         }
@@ -102,6 +668,15 @@ pub enum DataType {
     UnsignedInt,
 }
 
+// The kinds of memory access a program makes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    // an instruction fetch, which faults on pages with `NoExecute`
+    Fetch,
+}
+
 impl ValueType {
     pub fn get_value(&self) -> usize {
         match *self {
@@ -0,0 +1,456 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::mem::ptable::{Geometry, PagingMode, KERNBASE, PAGESIZE};
+use crate::mem::replace::PolicyKind;
+use crate::mem::tlb::TlbPolicy;
+
+pub const MIN_FRAMES: u32 = 4;
+pub const MAX_FRAMES: u32 = 1 << 20;
+// a 4-byte entry gives up the top bit of its page number to no-execute
+pub const MAX_FRAMES_32: u32 = 1 << 19;
+pub const MIN_PAGESIZE: usize = 4096;
+pub const MAX_PAGESIZE: usize = 65536;
+pub const WS_WINDOW: u64 = 1000;
+pub const MAX_EVENTS: usize = 1 << 16;
+// How far above the base the heap starts, leaving the addresses below it
+// to pointers. The same span above the heap base is kept for the heap.
+pub const HEAP_OFFSET: u64 = 0x10000000;
+
+// How much the simulator prints while it runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Verbosity {
+    Quiet,
+    // page fault lines such as PGZERO and PGCOPY
+    Faults,
+    // faults plus full memory dumps from `print`
+    Full,
+}
+
+impl From<bool> for Verbosity {
+    fn from(debug: bool) -> Self {
+        match debug {
+            true => Self::Full,
+            false => Self::Quiet,
+        }
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "0" | "quiet" | "false" | "off" => Ok(Self::Quiet),
+            "1" | "faults" => Ok(Self::Faults),
+            "2" | "full" | "true" | "on" => Ok(Self::Full),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Syntax { line: usize, text: String },
+    UnknownKey { line: usize, key: String },
+    BadValue { line: usize, key: String, value: String },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read config: {}", err),
+            Self::Syntax { line, text } => write!(f, "line {}: expected key = value, got \"{}\"", line, text),
+            Self::UnknownKey { line, key } => write!(f, "line {}: unknown key \"{}\"", line, key),
+            Self::BadValue { line, key, value } => write!(f, "line {}: bad value \"{}\" for {}", line, value, key),
+            Self::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+// Parameters of the simulated machine. Built with chained setters,
+//
+//     let config = SimulatorConfig::new().frames(64).page_size(8192).seed(7);
+//
+// or loaded from a file of `key = value` lines (see `parse`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulatorConfig {
+    frames: u32,
+    page_size: usize,
+    base: u64,
+    heap_base: Option<u64>,
+    paging: PagingMode,
+    debug: Verbosity,
+    seed: Option<u64>,
+    policy: PolicyKind,
+    tlb_entries: usize,
+    tlb_ways: usize,
+    tlb_policy: TlbPolicy,
+    asid: bool,
+    ws_window: u64,
+    ws_interval: u64,
+    pff_low: u64,
+    pff_high: u64,
+    max_events: usize,
+    swap: u32,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            frames: 32,
+            page_size: PAGESIZE,
+            base: KERNBASE,
+            heap_base: None,
+            paging: PagingMode::Bits32,
+            debug: Verbosity::Quiet,
+            seed: None,
+            policy: PolicyKind::Fifo,
+            tlb_entries: 16,
+            tlb_ways: 4,
+            tlb_policy: TlbPolicy::Lru,
+            asid: false,
+            ws_window: WS_WINDOW,
+            ws_interval: 0,
+            pff_low: 0,
+            pff_high: 0,
+            max_events: MAX_EVENTS,
+            swap: 0,
+        }
+    }
+}
+
+impl SimulatorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of physical frames, including the reserved zero page.
+    pub fn frames(mut self, frames: u32) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    // First virtual address handed out to pointers.
+    pub fn base(mut self, base: u64) -> Self {
+        self.base = base;
+        self
+    }
+
+    // Where the heap of every process starts until it execs an image.
+    pub fn heap_base(mut self, heap_base: u64) -> Self {
+        self.heap_base = Some(heap_base);
+        self
+    }
+
+    // Address width and so the number of page table levels.
+    pub fn paging(mut self, paging: PagingMode) -> Self {
+        self.paging = paging;
+        self
+    }
+
+    pub fn debug<V: Into<Verbosity>>(mut self, debug: V) -> Self {
+        self.debug = debug.into();
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn policy(mut self, policy: PolicyKind) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // TLB size and associativity; zero entries turns the TLB off.
+    pub fn tlb(mut self, entries: usize, ways: usize) -> Self {
+        self.tlb_entries = entries;
+        self.tlb_ways = ways;
+        self
+    }
+
+    pub fn tlb_policy(mut self, policy: TlbPolicy) -> Self {
+        self.tlb_policy = policy;
+        self
+    }
+
+    // Tag TLB entries with the pid instead of flushing on every switch.
+    pub fn asid(mut self, asid: bool) -> Self {
+        self.asid = asid;
+        self
+    }
+
+    // The working set window in references, which WSClock also uses,
+    // and how many references a process makes between samples of its
+    // working set; zero leaves working sets untracked.
+    pub fn working_set(mut self, window: u64, interval: u64) -> Self {
+        self.ws_window = window;
+        self.ws_interval = interval;
+        self
+    }
+
+    // Page-fault-frequency bounds in faults per thousand references. A
+    // process faulting more often is given more frames, one faulting
+    // less often has some taken away. Zero for `high` turns it off.
+    pub fn pff(mut self, low: u64, high: u64) -> Self {
+        self.pff_low = low;
+        self.pff_high = high;
+        self
+    }
+
+    // How many of the latest events the log keeps. Older ones are
+    // dropped, though `Stats` still counts them, and zero keeps none.
+    pub fn max_events(mut self, max_events: usize) -> Self {
+        self.max_events = max_events;
+        self
+    }
+
+    // Slots of a swap area in a temporary file that every simulator
+    // opens for itself; zero leaves it without swap.
+    pub fn swap(mut self, slots: u32) -> Self {
+        self.swap = slots;
+        self
+    }
+
+    pub fn get_frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn get_base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn get_heap_base(&self) -> u64 {
+        self.heap_base.unwrap_or(self.base.wrapping_add(HEAP_OFFSET))
+    }
+
+    pub fn get_paging(&self) -> PagingMode {
+        self.paging
+    }
+
+    pub fn get_debug(&self) -> Verbosity {
+        self.debug
+    }
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn get_policy(&self) -> PolicyKind {
+        self.policy
+    }
+
+    pub fn get_tlb_entries(&self) -> usize {
+        self.tlb_entries
+    }
+
+    pub fn get_tlb_ways(&self) -> usize {
+        self.tlb_ways
+    }
+
+    pub fn get_tlb_policy(&self) -> TlbPolicy {
+        self.tlb_policy
+    }
+
+    pub fn get_asid(&self) -> bool {
+        self.asid
+    }
+
+    pub fn get_ws_window(&self) -> u64 {
+        self.ws_window
+    }
+
+    pub fn get_ws_interval(&self) -> u64 {
+        self.ws_interval
+    }
+
+    pub fn get_pff(&self) -> Option<(u64, u64)> {
+        if self.pff_high > 0 {
+            Some((self.pff_low, self.pff_high))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_max_events(&self) -> usize {
+        self.max_events
+    }
+
+    pub fn get_swap(&self) -> u32 {
+        self.swap
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        Geometry::new(self.page_size, self.base, self.paging)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.frames < MIN_FRAMES || self.frames > MAX_FRAMES {
+            return Err(ConfigError::Invalid(format!(
+                "frames must be between {} and {}", MIN_FRAMES, MAX_FRAMES
+            )));
+        }
+        if self.paging == PagingMode::Bits32 && self.frames > MAX_FRAMES_32 {
+            return Err(ConfigError::Invalid(format!(
+                "32-bit paging holds at most {} frames", MAX_FRAMES_32
+            )));
+        }
+        if !self.page_size.is_power_of_two()
+            || self.page_size < MIN_PAGESIZE
+            || self.page_size > MAX_PAGESIZE {
+            return Err(ConfigError::Invalid(format!(
+                "page_size must be a power of two between {} and {}", MIN_PAGESIZE, MAX_PAGESIZE
+            )));
+        }
+        if self.base & (self.page_size as u64 - 1) != 0 {
+            return Err(ConfigError::Invalid("base must be page aligned".to_string()));
+        }
+        if !self.geometry().canonical(self.base) {
+            return Err(ConfigError::Invalid("base must be a canonical address for the paging mode".to_string()));
+        }
+        let heap_base = match self.heap_base {
+            Some(heap_base) => heap_base,
+            None => self.base.checked_add(HEAP_OFFSET)
+                .filter(|&heap_base| self.geometry().canonical(heap_base))
+                .ok_or_else(|| ConfigError::Invalid("base leaves no room for the heap above it, set heap_base".to_string()))?,
+        };
+        if heap_base % self.page_size as u64 != 0 || !self.geometry().canonical(heap_base) {
+            return Err(ConfigError::Invalid("heap_base must be a page aligned, canonical address".to_string()));
+        }
+        if self.tlb_entries > 0 && (self.tlb_ways == 0 || !self.tlb_entries.is_multiple_of(self.tlb_ways)) {
+            return Err(ConfigError::Invalid("tlb_entries must be a multiple of tlb_ways".to_string()));
+        }
+        if self.ws_window == 0 {
+            return Err(ConfigError::Invalid("ws_window must be at least one reference".to_string()));
+        }
+        if self.pff_high > 0 && self.ws_interval == 0 {
+            return Err(ConfigError::Invalid("pff needs ws_interval to sample working sets".to_string()));
+        }
+        if self.paging == PagingMode::Bits32 && self.swap > MAX_FRAMES_32 {
+            return Err(ConfigError::Invalid(format!(
+                "32-bit paging holds at most {} swap slots", MAX_FRAMES_32
+            )));
+        }
+        if self.pff_low > self.pff_high {
+            return Err(ConfigError::Invalid("pff_low must not be above pff_high".to_string()));
+        }
+        Ok(())
+    }
+
+    // Reads `key = value` lines. Blank lines and anything after a `#`
+    // are ignored, and keys that are left out keep their defaults.
+    //
+    //     frames = 64
+    //     page_size = 4096
+    //     base = 0x80000000
+    //     heap_base = 0x90000000  # where the heap starts, base + 256 MiB by default
+    //     paging = 48         # 32, 48 or 57 bit addresses
+    //     debug = faults      # quiet, faults or full
+    //     seed = 42
+    //     policy = clock      # fifo, lru, clock, wsclock or random
+    //     tlb_entries = 16    # 0 turns the TLB off
+    //     tlb_ways = 4
+    //     tlb_policy = lru    # lru, fifo or random
+    //     asid = true
+    //     ws_window = 1000    # references in a working set, also for wsclock
+    //     ws_interval = 100   # references between samples, 0 is off
+    //     pff_low = 5         # faults per thousand references
+    //     pff_high = 50       # 0 turns the controller off
+    //     max_events = 65536  # latest events kept in the log, 0 keeps none
+    //     swap = 256          # slots of a temporary swap area, 0 is none
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let text = match raw.find('#') {
+                Some(idx) => &raw[..idx],
+                None => raw,
+            }.trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match text.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(ConfigError::Syntax { line, text: text.to_string() }),
+            };
+            let bad_value = || ConfigError::BadValue {
+                line,
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+            match key {
+                "frames" => config.frames = parse_u32(value).ok_or_else(bad_value)?,
+                "page_size" => config.page_size = parse_u32(value).ok_or_else(bad_value)? as usize,
+                "base" => config.base = parse_num(value).ok_or_else(bad_value)?,
+                "heap_base" => config.heap_base = Some(parse_num(value).ok_or_else(bad_value)?),
+                "paging" => config.paging = value.parse().map_err(|_| bad_value())?,
+                "debug" => config.debug = value.parse().map_err(|_| bad_value())?,
+                "seed" => config.seed = Some(parse_num(value).ok_or_else(bad_value)?),
+                "policy" => config.policy = value.parse().map_err(|_| bad_value())?,
+                "tlb_entries" => config.tlb_entries = parse_u32(value).ok_or_else(bad_value)? as usize,
+                "tlb_ways" => config.tlb_ways = parse_u32(value).ok_or_else(bad_value)? as usize,
+                "tlb_policy" => config.tlb_policy = value.parse().map_err(|_| bad_value())?,
+                "asid" => config.asid = parse_bool(value).ok_or_else(bad_value)?,
+                "ws_window" => config.ws_window = parse_num(value).ok_or_else(bad_value)?,
+                "ws_interval" => config.ws_interval = parse_num(value).ok_or_else(bad_value)?,
+                "pff_low" => config.pff_low = parse_num(value).ok_or_else(bad_value)?,
+                "pff_high" => config.pff_high = parse_num(value).ok_or_else(bad_value)?,
+                "max_events" => config.max_events = parse_num(value).ok_or_else(bad_value)? as usize,
+                "swap" => config.swap = parse_u32(value).ok_or_else(bad_value)?,
+                _ => return Err(ConfigError::UnknownKey { line, key: key.to_string() }),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+fn parse_num(value: &str) -> Option<u64> {
+    let value = value.replace('_', "");
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_u32(value: &str) -> Option<u32> {
+    parse_num(value).and_then(|num| u32::try_from(num).ok())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
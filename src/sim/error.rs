@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+// Why a simulator operation could not be carried out.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SimError {
+    // the virtual address is not mapped by the current process
+    InvalidAddress(u64),
+    // `register` was called for an address already registered, or mapped some other way
+    AlreadyMapped(u64),
+    // the process is sleeping or has exited
+    NotRunning(u32),
+    // `wait` was called by a process without children
+    NoChildren(u32),
+    // page replacement could not free a frame
+    OutOfMemory,
+    // a user write to a kernel page, or an access the mapping does not allow
+    ProtectionFault(u64),
+    // the address lies outside the virtual address space of the paging mode
+    NonCanonical(u64),
+    NoSuchProcess(u32),
+    // an argument was out of range, e.g. a misaligned file offset
+    InvalidArgument(&'static str),
+    // a mapped file could not be read or written
+    Io(String),
+    // the simulator was started with a config that fails `validate`
+    InvalidConfig(String),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidAddress(vaddr) => write!(f, "invalid address 0x{:x}", vaddr),
+            Self::AlreadyMapped(vaddr) => write!(f, "mapping already registered for 0x{:x}", vaddr),
+            Self::NotRunning(pid) => write!(f, "process {} is not running", pid),
+            Self::NoChildren(pid) => write!(f, "process {} has no children", pid),
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::ProtectionFault(vaddr) => write!(f, "protection fault at 0x{:x}", vaddr),
+            Self::NonCanonical(vaddr) => write!(f, "non-canonical address 0x{:x}", vaddr),
+            Self::NoSuchProcess(pid) => write!(f, "no process {}", pid),
+            Self::InvalidArgument(what) => write!(f, "invalid argument: {}", what),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::InvalidConfig(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+impl From<io::Error> for SimError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err.to_string())
+    }
+}
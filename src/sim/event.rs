@@ -0,0 +1,229 @@
+use std::collections::{vec_deque, HashMap, VecDeque};
+use std::io::{self, Write};
+
+use super::stats::Stats;
+
+// Something the kernel did. Addresses are page-aligned virtual addresses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    Kalloc { ppn: u32 },
+    Kfree { ppn: u32 },
+    Map { va: u64, ppn: u32 },
+    // a page, or a whole large page, was unmapped
+    Unmap { va: u64 },
+    // a write faulted on the zero page and got a fresh frame
+    ZeroFill { va: u64, ppn: u32 },
+    // a write faulted on a shared page and got a private copy
+    CopyOnWrite { va: u64, from: u32, to: u32 },
+    // a write faulted on a read-only page nobody else shares
+    Upgrade { va: u64, ppn: u32 },
+    // a page of a mapped file was dropped from its frame, to be read in
+    // again on its next access
+    Evict { ppn: u32 },
+    SwapOut { ppn: u32, slot: u32 },
+    SwapIn { va: u64, ppn: u32, slot: u32 },
+    // an access faulted on a page of a mapped file and read it in
+    FileIn { va: u64, ppn: u32 },
+    // a dirty page of a shared file mapping was written to its file
+    Writeback { ppn: u32 },
+    // a dirty page could not be written to its file as its process
+    // exited, and its changes were lost
+    WritebackFailed { ppn: u32 },
+    // an access to an unmapped address or a user write to a kernel page
+    InvalidAccess { va: u64 },
+    Fork { child: u32 },
+    Switch { to: u32 },
+    Kill,
+    Exit { status: u8 },
+    // the running process threw its address space away for a new image
+    Exec,
+    // the running process reaped a child that had exited
+    Wait { child: u32, status: u8 },
+    // a sample of the running process's working set after `refs` of its
+    // references, and the frames PFF lets it keep, zero without PFF
+    WorkingSet { refs: u64, pages: u64, resident: u64, limit: u64 },
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Kalloc { .. } => "kalloc",
+            Self::Kfree { .. } => "kfree",
+            Self::Map { .. } => "map",
+            Self::Unmap { .. } => "unmap",
+            Self::ZeroFill { .. } => "zero_fill",
+            Self::CopyOnWrite { .. } => "cow_copy",
+            Self::Upgrade { .. } => "upgrade",
+            Self::Evict { .. } => "evict",
+            Self::SwapOut { .. } => "swap_out",
+            Self::SwapIn { .. } => "swap_in",
+            Self::FileIn { .. } => "file_in",
+            Self::Writeback { .. } => "writeback",
+            Self::WritebackFailed { .. } => "writeback_failed",
+            Self::InvalidAccess { .. } => "invalid_access",
+            Self::Fork { .. } => "fork",
+            Self::Switch { .. } => "switch",
+            Self::Kill => "kill",
+            Self::Exit { .. } => "exit",
+            Self::Exec => "exec",
+            Self::Wait { .. } => "wait",
+            Self::WorkingSet { .. } => "working_set",
+        }
+    }
+
+    pub fn fields(&self) -> Vec<(&'static str, u64)> {
+        match *self {
+            Self::Kalloc { ppn } | Self::Kfree { ppn } | Self::Evict { ppn } | Self::Writeback { ppn } | Self::WritebackFailed { ppn } =>
+                vec![("ppn", ppn as u64)],
+            Self::Map { va, ppn } | Self::ZeroFill { va, ppn } | Self::Upgrade { va, ppn } | Self::FileIn { va, ppn } =>
+                vec![("va", va), ("ppn", ppn as u64)],
+            Self::CopyOnWrite { va, from, to } => vec![("va", va), ("from", from as u64), ("to", to as u64)],
+            Self::SwapOut { ppn, slot } => vec![("ppn", ppn as u64), ("slot", slot as u64)],
+            Self::SwapIn { va, ppn, slot } => vec![("va", va), ("ppn", ppn as u64), ("slot", slot as u64)],
+            Self::InvalidAccess { va } | Self::Unmap { va } => vec![("va", va)],
+            Self::Fork { child } => vec![("child", child as u64)],
+            Self::Switch { to } => vec![("to", to as u64)],
+            Self::Kill | Self::Exec => vec![],
+            Self::Exit { status } => vec![("status", status as u64)],
+            Self::Wait { child, status } => vec![("child", child as u64), ("status", status as u64)],
+            Self::WorkingSet { refs, pages, resident, limit } =>
+                vec![("refs", refs), ("pages", pages), ("resident", resident), ("limit", limit)],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    // logical clock, one tick per event
+    pub time: u64,
+    // the process running when the event happened
+    pub pid: u32,
+    pub kind: EventKind,
+}
+
+// The latest events of one simulated machine, in order, along with
+// running totals for the machine and for each process. The totals count
+// every event, including those dropped to keep the log under `limit`.
+#[derive(Default)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    limit: usize,
+    // the next tick of the clock
+    time: u64,
+    pid: u32,
+    totals: Stats,
+    per_pid: HashMap<u32, Stats>,
+}
+
+impl EventLog {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, ..Self::default() }
+    }
+
+    pub fn record(&mut self, kind: EventKind) {
+        let time = self.time;
+        self.time += 1;
+        self.totals.count(&kind);
+        self.per_pid.entry(self.pid).or_default().count(&kind);
+        if self.limit == 0 {
+            return;
+        }
+        if self.events.len() == self.limit {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event { time, pid: self.pid, kind });
+    }
+
+    pub fn totals(&self) -> Stats {
+        self.totals
+    }
+
+    pub fn totals_for(&self, pid: u32) -> Stats {
+        self.per_pid.get(&pid).copied().unwrap_or_default()
+    }
+
+    // Attributes later events to `pid`.
+    pub fn set_pid(&mut self, pid: u32) {
+        self.pid = pid;
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, Event> {
+        self.events.iter()
+    }
+
+    // The events still in the log.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    // Events that were recorded and then dropped for the limit.
+    pub fn dropped(&self) -> u64 {
+        self.time - self.events.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // One JSON object per line,
+    //
+    //     {"time":3,"pid":0,"event":"map","va":2147483648,"ppn":0}
+    pub fn write_jsonl<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for event in &self.events {
+            write!(out, "{{\"time\":{},\"pid\":{},\"event\":\"{}\"", event.time, event.pid, event.kind.name())?;
+            for (key, value) in event.kind.fields() {
+                write!(out, ",\"{}\":{}", key, value)?;
+            }
+            writeln!(out, "}}")?;
+        }
+        Ok(())
+    }
+
+    // Every working set sample as CSV, one row per sample of a process.
+    pub fn write_working_sets<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "time,pid,refs,pages,resident,limit")?;
+        for event in &self.events {
+            if let EventKind::WorkingSet { refs, pages, resident, limit } = event.kind {
+                writeln!(out, "{},{},{},{},{},{}", event.time, event.pid, refs, pages, resident, limit)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The Chrome trace-event format read by chrome://tracing and Perfetto.
+    // Every event is an instant on its process's track, one microsecond
+    // per tick of the logical clock, except working set samples, which
+    // are counters so they are drawn as a graph.
+    pub fn write_chrome_trace<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{{\"traceEvents\":[")?;
+        for (i, event) in self.events.iter().enumerate() {
+            let phase = match event.kind {
+                EventKind::WorkingSet { .. } => "\"ph\":\"C\"",
+                _ => "\"ph\":\"i\",\"s\":\"t\"",
+            };
+            write!(
+                out,
+                "{{\"name\":\"{}\",\"cat\":\"vmem\",{},\"ts\":{},\"pid\":{},\"tid\":{},\"args\":{{",
+                event.kind.name(), phase, event.time, event.pid, event.pid
+            )?;
+            for (j, (key, value)) in event.kind.fields().into_iter().enumerate() {
+                if j > 0 {
+                    write!(out, ",")?;
+                }
+                write!(out, "\"{}\":{}", key, value)?;
+            }
+            let sep = if i + 1 < self.events.len() { "," } else { "" };
+            writeln!(out, "}}}}{}", sep)?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+impl<'a> IntoIterator for &'a EventLog {
+    type Item = &'a Event;
+    type IntoIter = vec_deque::Iter<'a, Event>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
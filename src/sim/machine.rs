@@ -0,0 +1,70 @@
+use crate::mem::alloc::Memory;
+use crate::mem::replace::ReplacementPolicy;
+use crate::mem::tlb::Tlb;
+use super::config::{SimulatorConfig, HEAP_OFFSET};
+use super::event::EventLog;
+
+// Everything one simulated machine owns: its physical memory and the
+// next virtual address to hand out to a pointer. Every simulator owns
+// its own machine, so simulators never see each other's state.
+pub struct Machine {
+    mem: Memory,
+    vaddr: u64,
+    // where heaps grow, which is never handed out
+    heap: (u64, u64),
+}
+
+impl Machine {
+    pub fn new(config: &SimulatorConfig, policy: Box<dyn ReplacementPolicy>) -> Self {
+        let geo = config.geometry();
+        let tlb = Tlb::new(
+            config.get_tlb_entries(),
+            config.get_tlb_ways(),
+            geo.page_size(),
+            geo.large_page_size(),
+            config.get_tlb_policy(),
+            config.get_asid(),
+            config.get_seed(),
+        );
+        Self {
+            mem: Memory::new(config.get_frames(), &geo, policy, tlb, EventLog::new(config.get_max_events())),
+            vaddr: config.get_base(),
+            heap: (config.get_heap_base(), config.get_heap_base().saturating_add(HEAP_OFFSET)),
+        }
+    }
+
+    pub fn mem(&self) -> &Memory {
+        &self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut Memory {
+        &mut self.mem
+    }
+
+    // Reserves `size` bytes of virtual address space.
+    pub fn next_vaddr(&mut self, size: usize) -> u64 {
+        let vaddr = self.skip_heap(self.vaddr, size as u64);
+        self.vaddr = vaddr.wrapping_add(size as u64);
+        vaddr
+    }
+
+    // Reserves `len` bytes of virtual address space starting on a page
+    // boundary, for mappings that cover whole pages.
+    pub fn next_region(&mut self, len: u64) -> u64 {
+        let page_size = self.mem.page_size() as u64;
+        let start = self.vaddr.div_ceil(page_size).wrapping_mul(page_size);
+        let start = self.skip_heap(start, len);
+        self.vaddr = start.wrapping_add(len);
+        start
+    }
+
+    // Moves a range that would run into the heap's span past it.
+    fn skip_heap(&self, start: u64, len: u64) -> u64 {
+        let (heap, heap_end) = self.heap;
+        if start < heap_end && heap < start.saturating_add(len) {
+            heap_end
+        } else {
+            start
+        }
+    }
+}
@@ -1,2 +1,10 @@
 pub mod check;
-pub mod pointer;
\ No newline at end of file
+pub mod config;
+pub mod error;
+pub mod event;
+pub mod machine;
+pub mod pointer;
+pub mod sched;
+pub mod script;
+pub mod stats;
+pub mod trace;
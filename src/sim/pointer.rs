@@ -1,31 +1,30 @@
 extern crate raw_pointer as rptr;
 
-use crate::mem::ptable::KERNBASE;
 use core::ops::{Deref, DerefMut};
-use core::convert::From;
 
 pub struct Pointer<T> {
-    vaddr: u32,
+    vaddr: u64,
     ptr: rptr::Pointer<T>
 }
 
-static mut VADDR: u32 = KERNBASE;
-
+// Virtual addresses are handed out by the simulator that owns the
+// address space, see `Simulator::pointer`.
 impl<T> Pointer<T> {
-    pub fn new(ptr: &mut T) -> Self {
-        let vaddr = unsafe {
-            VADDR
-        };
-        unsafe {
-            VADDR += std::mem::size_of::<T>() as u32;
-        }
+    pub fn new(vaddr: u64, ptr: &mut T) -> Self {
         Self {
             vaddr,
             ptr: rptr::Pointer::new(ptr)
         }
     }
 
-    pub fn vaddr(&self) -> u32 {
+    pub fn from_addr(vaddr: u64, addr: usize) -> Self {
+        Self {
+            vaddr,
+            ptr: rptr::Pointer::from(addr)
+        }
+    }
+
+    pub fn vaddr(&self) -> u64 {
         self.vaddr
     }
 
@@ -40,37 +39,22 @@ impl<T> Pointer<T> {
 
 impl<T> Clone for Pointer<T> {
     fn clone(&self) -> Pointer<T> {
-        Pointer::new(self.unwrap_mut())
+        *self
     }
 }
 
 impl<T> Copy for Pointer<T> {}
 
-impl<T> From<usize> for Pointer<T> {
-    fn from(item: usize) -> Self {
-        let vaddr = unsafe {
-            VADDR
-        };
-        unsafe {
-            VADDR += std::mem::size_of::<T>() as u32;
-        }
-        Self {
-            vaddr,
-            ptr: rptr::Pointer::from(item)
-        }
-    }
-}
-
 impl<T> Deref for Pointer<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-       & *self.ptr
+        &self.ptr
     }
 }
 
 impl<T> DerefMut for Pointer<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut *self.ptr
+        &mut self.ptr
     }
 }
\ No newline at end of file
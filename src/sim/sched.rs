@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use super::check::Simulator;
+use super::error::SimError;
+use super::trace::{self, TraceOp};
+
+pub const QUANTUM: u64 = 10;
+
+// How the scheduler picks the next process to run when a quantum ends.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SchedPolicy {
+    // every ready process in turn
+    RoundRobin,
+    // the ready process with the highest priority, taking turns with
+    // any of the same priority; lower ones wait until those are done
+    Priority,
+}
+
+impl FromStr for SchedPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rr" | "round-robin" => Ok(Self::RoundRobin),
+            "priority" | "prio" => Ok(Self::Priority),
+            _ => Err(()),
+        }
+    }
+}
+
+// A process and the operations it has left to run.
+struct Task {
+    pid: u32,
+    priority: u32,
+    ops: VecDeque<TraceOp>,
+    report: TaskReport,
+}
+
+// What one process did while it was scheduled. Times are timer ticks,
+// one per operation run by any process.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TaskReport {
+    pub pid: u32,
+    pub priority: u32,
+    pub ops: u64,
+    pub faults: u64,
+    // times the process was switched to
+    pub dispatches: u64,
+    // the tick its last operation ran on
+    pub finished: u64,
+    // why it stopped early, if it did
+    pub error: Option<SimError>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SchedReport {
+    pub ticks: u64,
+    pub switches: u64,
+    pub tasks: Vec<TaskReport>,
+}
+
+impl fmt::Display for SchedReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Ticks:          {:>10}", self.ticks)?;
+        writeln!(f, "Switches:       {:>10}", self.switches)?;
+        writeln!(f, "{:>6} {:>8} {:>8} {:>8} {:>10} {:>8} {:>8}", "Pid", "Prio", "Ops", "Faults", "Dispatches", "Finished", "Waited")?;
+        for task in &self.tasks {
+            write!(
+                f,
+                "{:>6} {:>8} {:>8} {:>8} {:>10} {:>8} {:>8}",
+                task.pid, task.priority, task.ops, task.faults, task.dispatches, task.finished, task.finished - task.ops
+            )?;
+            match &task.error {
+                Some(err) => writeln!(f, "  {}", err)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs several processes at once, each replaying its own stream of
+// memory references, and switches between them on a timer instead of
+// at the user's say. Each process is forked from the one that was
+// current when it was spawned, and every operation is a tick of the
+// timer. When a process has used up its quantum it is put to sleep and
+// the policy wakes up the next, through `Simulator::switch`.
+pub struct Scheduler {
+    policy: SchedPolicy,
+    quantum: u64,
+    tasks: Vec<Task>,
+}
+
+impl Scheduler {
+    pub fn new(policy: SchedPolicy, quantum: u64) -> Self {
+        Self {
+            policy,
+            quantum: quantum.max(1),
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> SchedPolicy {
+        self.policy
+    }
+
+    pub fn quantum(&self) -> u64 {
+        self.quantum
+    }
+
+    // Forks a process to run `ops` at `priority`, higher going first
+    // under `SchedPolicy::Priority`, and returns its pid. The parent
+    // stays current so it can spawn the next one. Only accesses can be
+    // scheduled, since forks and switches are the scheduler's to make.
+    pub fn spawn<I: IntoIterator<Item = TraceOp>>(&mut self, sim: &mut Simulator, ops: I, priority: u32) -> Result<u32, SimError> {
+        let ops: VecDeque<TraceOp> = ops.into_iter().collect();
+        if ops.iter().any(|op| !matches!(op, TraceOp::Access { .. })) {
+            return Err(SimError::InvalidArgument("only accesses can be scheduled"));
+        }
+        let parent = sim.current();
+        let pid = sim.fork()?;
+        sim.switch(parent)?;
+        self.tasks.push(Task {
+            pid,
+            priority,
+            ops,
+            report: TaskReport { pid, priority, ..TaskReport::default() },
+        });
+        Ok(pid)
+    }
+
+    // Takes over the processes another scheduler spawned but never ran.
+    pub fn adopt(&mut self, other: Scheduler) {
+        self.tasks.extend(other.tasks);
+    }
+
+    // Runs every spawned process until it has no operations left, or
+    // one of them fails, which only stops that process. A process that
+    // exited before its turn is skipped with the error. The process that
+    // was current before is current again afterwards.
+    pub fn run(&mut self, sim: &mut Simulator) -> Result<SchedReport, SimError> {
+        let page_size = sim.config().get_page_size() as u64;
+        let parent = sim.current();
+        let mut report = SchedReport::default();
+        let mut ready: VecDeque<usize> = (0..self.tasks.len()).collect();
+        let mut running = None;
+        while let Some(t) = self.next(&mut ready) {
+            let task = &mut self.tasks[t];
+            if running != Some(t) {
+                if let Err(err) = sim.switch(task.pid) {
+                    task.report.error = Some(err);
+                    task.ops.clear();
+                    continue;
+                }
+                report.switches += 1;
+                task.report.dispatches += 1;
+                running = Some(t);
+            }
+
+            let before = sim.event_log().totals_for(task.pid).faults.total();
+            for _ in 0..self.quantum {
+                let op = match task.ops.pop_front() {
+                    Some(op) => op,
+                    None => break
+                };
+                report.ticks += 1;
+                task.report.ops += 1;
+                task.report.finished = report.ticks;
+                if let Err(err) = trace::step(sim, op, page_size, &mut |_, _| {}) {
+                    task.report.error = Some(err);
+                    task.ops.clear();
+                    break;
+                }
+            }
+            task.report.faults += sim.event_log().totals_for(task.pid).faults.total() - before;
+
+            // the timer went off, or the process is done
+            if !task.ops.is_empty() {
+                ready.push_back(t);
+            }
+        }
+        report.tasks = self.tasks.drain(..).map(|task| task.report).collect();
+        if running.is_some() {
+            sim.switch(parent)?;
+        }
+        Ok(report)
+    }
+
+    fn next(&self, ready: &mut VecDeque<usize>) -> Option<usize> {
+        let idx = match self.policy {
+            SchedPolicy::RoundRobin => 0,
+            SchedPolicy::Priority => {
+                let top = ready.iter().map(|&t| self.tasks[t].priority).max()?;
+                ready.iter().position(|&t| self.tasks[t].priority == top)?
+            },
+        };
+        ready.remove(idx)
+    }
+}
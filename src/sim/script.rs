@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::proc::image::{Image, ImageError};
+use super::check::{DataType, Simulator, ValueType};
+use super::error::SimError;
+use super::pointer::Pointer;
+use super::sched::{SchedPolicy, Scheduler, QUANTUM};
+use super::trace::{self, Trace, TraceError, TraceFormat};
+
+// Every command with its arguments and what it does, for `help` and for
+// usage errors.
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("var", "var <name>...", "reserve addresses for variables without mapping them"),
+    ("register", "register <name>", "map the page holding a variable, declaring it if needed"),
+    ("unregister", "unregister <name>", "drop a variable, and its page once no other is registered on it"),
+    ("write", "write <name> <value>", "write a number, negative numbers are signed"),
+    ("read", "read <name> [signed]", "print the value of a variable"),
+    ("lock", "lock <name>", "pin the page holding a variable in memory"),
+    ("unlock", "unlock <name>", "let the page holding a variable be evicted again"),
+    ("fork", "fork", "fork the current process and switch to the child"),
+    ("switch", "switch <pid>", "switch to the process with pid"),
+    ("kill", "kill", "kill the current process"),
+    ("exec", "exec <path>", "replace the current process with the image described in a file"),
+    ("exit", "exit <status>", "exit the current process and switch to its parent"),
+    ("wait", "wait", "reap a child of the current process that has exited"),
+    ("sbrk", "sbrk <delta>", "grow or shrink the heap by delta bytes, printing the old break"),
+    ("brk", "brk [addr]", "set the program break, then print where the heap starts and the break"),
+    ("print", "print", "dump every page table, with debug = full"),
+    ("ps", "ps", "list every process with its state, parent and resident pages"),
+    ("stats", "stats [pid]", "print memory statistics for the machine or a process"),
+    ("ws", "ws <pid>", "print the working set size of a process"),
+    ("replay", "replay <path> [format]", "replay a native, lackey or din memory trace"),
+    ("compare", "compare <path> [format]", "replay a trace under every policy and OPT"),
+    ("sweep", "sweep <path> <frames> [format]", "compare the policies at user frame counts min-max[/step]"),
+    ("scheduler", "scheduler <rr|priority> [quantum]", "pick how spawned processes take turns"),
+    ("spawn", "spawn <path> [priority] [format]", "fork a process that will replay a trace"),
+    ("run", "run", "run every spawned process until they are done"),
+    ("help", "help", "list the commands"),
+    ("quit", "quit", "stop reading commands"),
+];
+
+#[derive(Debug)]
+pub enum ScriptError {
+    UnknownCommand(String),
+    Usage(&'static str),
+    UnknownVariable(String),
+    BadNumber(String),
+    BadFormat(String),
+    BadPolicy(String),
+    Sim(SimError),
+    Trace(TraceError),
+    Image(ImageError),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(cmd) => write!(f, "unknown command \"{}\", try help", cmd),
+            Self::Usage(usage) => write!(f, "usage: {}", usage),
+            Self::UnknownVariable(name) => write!(f, "unknown variable \"{}\"", name),
+            Self::BadNumber(text) => write!(f, "bad number \"{}\"", text),
+            Self::BadFormat(text) => write!(f, "unknown trace format \"{}\"", text),
+            Self::BadPolicy(text) => write!(f, "unknown scheduling policy \"{}\"", text),
+            Self::Sim(err) => write!(f, "{}", err),
+            Self::Trace(err) => write!(f, "{}", err),
+            Self::Image(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<SimError> for ScriptError {
+    fn from(err: SimError) -> Self {
+        Self::Sim(err)
+    }
+}
+
+impl From<ImageError> for ScriptError {
+    fn from(err: ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+impl From<TraceError> for ScriptError {
+    fn from(err: TraceError) -> Self {
+        Self::Trace(err)
+    }
+}
+
+// Drives a simulator with lines of text, so scenarios can be run without
+// writing Rust. Variables are names for the pointers the commands take,
+// each one word wide and handed out in order like `Simulator::pointer`.
+pub struct Interpreter {
+    sim: Simulator,
+    vars: HashMap<String, Pointer<u64>>,
+    // processes spawned and waiting for `run`
+    sched: Scheduler,
+    finished: bool,
+}
+
+impl Interpreter {
+    pub fn new(sim: Simulator) -> Self {
+        Self {
+            sim,
+            vars: HashMap::new(),
+            sched: Scheduler::new(SchedPolicy::RoundRobin, QUANTUM),
+            finished: false,
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.sim
+    }
+
+    // Whether `quit` has been run.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // Runs one line. Blank lines and everything after a `#` are ignored.
+    pub fn exec(&mut self, line: &str) -> Result<(), ScriptError> {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (&cmd, args) = match words.split_first() {
+            Some(split) => split,
+            None => return Ok(())
+        };
+        match (cmd, args) {
+            ("var", names) if !names.is_empty() => {
+                for name in names {
+                    self.declare(name);
+                }
+            },
+            ("register", [name]) => {
+                let ptr = self.declare(name);
+                self.sim.register(ptr)?;
+            },
+            ("unregister", [name]) => self.sim.unregister(self.var(name)?)?,
+            ("write", [name, value]) => {
+                let value = parse_value(value)?;
+                self.sim.write(self.var(name)?, value)?;
+            },
+            ("read", [name]) => {
+                let value = self.sim.read(self.var(name)?, DataType::UnsignedInt)?;
+                println!("{} = {}", name, value.get_value());
+            },
+            ("read", [name, "signed"]) => {
+                let value = self.sim.read(self.var(name)?, DataType::SignedInt)?;
+                println!("{} = {}", name, value.get_value() as isize);
+            },
+            ("lock", [name]) => self.sim.lock(self.var(name)?)?,
+            ("unlock", [name]) => self.sim.unlock(self.var(name)?)?,
+            ("fork", []) => {
+                let pid = self.sim.fork()?;
+                println!("forked pid {}", pid);
+            },
+            ("switch", [pid]) => self.sim.switch(parse_pid(pid)?)?,
+            ("kill", []) => self.sim.kill()?,
+            ("exec", [path]) => match self.sim.exec(&Image::from_file(path)?)? {
+                Some(sp) => println!("exec {}, stack pointer 0x{:x}", path, sp),
+                None => println!("exec {}", path),
+            },
+            ("exit", [status]) => {
+                let status = status.parse().map_err(|_| ScriptError::BadNumber(status.to_string()))?;
+                self.sim.exit(status)?;
+            },
+            ("wait", []) => match self.sim.wait()? {
+                Some((pid, status)) => println!("reaped pid {} with status {}", pid, status),
+                None => println!("no child has exited"),
+            },
+            ("sbrk", [delta]) => {
+                let old = self.sim.sbrk(parse_delta(delta)?)?;
+                println!("break 0x{:x} -> 0x{:x}", old, self.sim.heap()?.1);
+            },
+            ("brk", []) => {
+                let (start, brk) = self.sim.heap()?;
+                println!("heap 0x{:x}, break 0x{:x}", start, brk);
+            },
+            ("brk", [addr]) => {
+                self.sim.brk(parse_number(addr)? as u64)?;
+                let (start, brk) = self.sim.heap()?;
+                println!("heap 0x{:x}, break 0x{:x}", start, brk);
+            },
+            ("print", []) => self.sim.print(),
+            ("stats", []) => print!("{}", self.sim.meminfo()),
+            ("ps", []) => print!("{}", self.sim.ps()),
+            ("stats", [pid]) => print!("{}", self.sim.process_stats(parse_pid(pid)?)?),
+            ("ws", [pid]) => println!("{} pages", self.sim.working_set(parse_pid(pid)?)?),
+            ("replay", [path]) => print!("{}", self.sim.replay(path, None)?),
+            ("replay", [path, format]) => print!("{}", self.sim.replay(path, Some(parse_format(format)?))?),
+            ("compare", [path]) => self.compare(path, None)?,
+            ("compare", [path, format]) => self.compare(path, Some(parse_format(format)?))?,
+            ("sweep", [path, frames]) => self.sweep(path, frames, None)?,
+            ("sweep", [path, frames, format]) => self.sweep(path, frames, Some(parse_format(format)?))?,
+            ("scheduler", [policy]) => self.scheduler(policy, QUANTUM)?,
+            ("scheduler", [policy, quantum]) => self.scheduler(policy, parse_number(quantum)? as u64)?,
+            ("spawn", [path]) => self.spawn(path, 0, None)?,
+            ("spawn", [path, priority]) => self.spawn(path, parse_number(priority)? as u32, None)?,
+            ("spawn", [path, priority, format]) => {
+                self.spawn(path, parse_number(priority)? as u32, Some(parse_format(format)?))?;
+            },
+            ("run", []) => print!("{}", self.sched.run(&mut self.sim)?),
+            ("help", []) => {
+                for (_, usage, what) in COMMANDS {
+                    println!("{:<34} {}", usage, what);
+                }
+            },
+            ("quit", []) => self.finished = true,
+            _ => {
+                return match COMMANDS.iter().find(|(name, _, _)| *name == cmd) {
+                    Some((_, usage, _)) => Err(ScriptError::Usage(usage)),
+                    None => Err(ScriptError::UnknownCommand(cmd.to_string())),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    // The pointer a name stands for, handing out the next address if
+    // the name is new.
+    fn declare(&mut self, name: &str) -> Pointer<u64> {
+        if let Some(&ptr) = self.vars.get(name) {
+            return ptr;
+        }
+        let ptr = self.sim.pointer_from::<u64>(0);
+        self.vars.insert(name.to_string(), ptr);
+        ptr
+    }
+
+    // Runs on fresh machines like this one, leaving this one alone.
+    fn compare(&self, path: &str, format: Option<TraceFormat>) -> Result<(), ScriptError> {
+        let trace = Trace::open(path, format)?;
+        print!("{}", trace::compare(self.sim.config(), &trace)?);
+        Ok(())
+    }
+
+    fn sweep(&self, path: &str, frames: &str, format: Option<TraceFormat>) -> Result<(), ScriptError> {
+        let frames = trace::parse_frames(frames).ok_or_else(|| ScriptError::BadNumber(frames.to_string()))?;
+        let trace = Trace::open(path, format)?;
+        print!("{}", trace::sweep(self.sim.config(), &trace, frames)?);
+        Ok(())
+    }
+
+    // Starts over with a new scheduler; processes already spawned keep
+    // waiting to run under it.
+    fn scheduler(&mut self, policy: &str, quantum: u64) -> Result<(), ScriptError> {
+        let policy = policy.parse().map_err(|_| ScriptError::BadPolicy(policy.to_string()))?;
+        let old = std::mem::replace(&mut self.sched, Scheduler::new(policy, quantum));
+        self.sched.adopt(old);
+        Ok(())
+    }
+
+    fn spawn(&mut self, path: &str, priority: u32, format: Option<TraceFormat>) -> Result<(), ScriptError> {
+        let trace = Trace::open(path, format)?;
+        let pid = self.sched.spawn(&mut self.sim, trace.ops(), priority)?;
+        println!("spawned pid {}", pid);
+        Ok(())
+    }
+
+    fn var(&self, name: &str) -> Result<Pointer<u64>, ScriptError> {
+        self.vars.get(name).copied().ok_or_else(|| ScriptError::UnknownVariable(name.to_string()))
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, ScriptError> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| ScriptError::BadNumber(text.to_string()))
+}
+
+fn parse_delta(text: &str) -> Result<i64, ScriptError> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let delta = i64::try_from(parse_number(digits)?).map_err(|_| ScriptError::BadNumber(text.to_string()))?;
+    if negative {
+        Ok(-delta)
+    } else {
+        Ok(delta)
+    }
+}
+
+fn parse_pid(text: &str) -> Result<u32, ScriptError> {
+    text.parse().map_err(|_| ScriptError::BadNumber(text.to_string()))
+}
+
+fn parse_format(text: &str) -> Result<TraceFormat, ScriptError> {
+    text.parse().map_err(|_| ScriptError::BadFormat(text.to_string()))
+}
+
+fn parse_value(text: &str) -> Result<ValueType, ScriptError> {
+    if text.starts_with('-') {
+        return text.parse()
+            .map(ValueType::SignedInt)
+            .map_err(|_| ScriptError::BadNumber(text.to_string()));
+    }
+    parse_number(text).map(ValueType::UnsignedInt)
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use super::event::EventKind;
+use crate::mem::tlb::TlbStats;
+use crate::proc::proc::ProcessState;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Faults {
+    pub zero_fill: u64,
+    pub cow_copy: u64,
+    pub cow_upgrade: u64,
+    pub swap_in: u64,
+    pub file_in: u64,
+    // accesses to unmapped addresses and protection faults
+    pub invalid: u64,
+}
+
+impl Faults {
+    pub fn total(&self) -> u64 {
+        self.zero_fill + self.cow_copy + self.cow_upgrade + self.swap_in + self.file_in + self.invalid
+    }
+}
+
+// Page walks and the table entries they read, the cost a TLB hit saves.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Walks {
+    pub count: u64,
+    pub reads: u64,
+}
+
+impl Walks {
+    pub fn average(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.reads as f64 / count as f64,
+        }
+    }
+}
+
+// Memory statistics for the whole machine or for a single process.
+// The counters run from the start of the simulation while the frame
+// counts are a snapshot taken when the stats were requested.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub faults: Faults,
+    pub frames_allocated: u64,
+    pub frames_freed: u64,
+    pub evictions: u64,
+    pub swap_outs: u64,
+    // dirty pages of shared file mappings written back to their files
+    pub writebacks: u64,
+    pub tlb: TlbStats,
+    pub walks: Walks,
+
+    pub page_size: usize,
+    pub total_frames: usize,
+    pub free_frames: usize,
+    // page directories and page tables
+    pub table_frames: usize,
+    // frames holding user data
+    pub data_frames: usize,
+    // data frames mapped more than once, e.g. by both sides of a fork
+    pub shared_frames: usize,
+    pub private_frames: usize,
+    // user pages backed by a frame, counted once per mapping
+    pub resident_pages: usize,
+    pub swapped_pages: usize,
+    // frames in the page cache of mapped files
+    pub cached_frames: usize,
+    // cached frames that hold pages of shared memory segments
+    pub shmem_frames: usize,
+    // large pages mapped, whose frames are also counted above
+    pub large_pages: usize,
+    pub large_page_size: usize,
+    // bytes of address space the TLB currently translates
+    pub tlb_reach: usize,
+}
+
+impl Stats {
+    pub(crate) fn count(&mut self, kind: &EventKind) {
+        match kind {
+            EventKind::Kalloc { .. } => self.frames_allocated += 1,
+            EventKind::Kfree { .. } => self.frames_freed += 1,
+            EventKind::ZeroFill { .. } => self.faults.zero_fill += 1,
+            EventKind::CopyOnWrite { .. } => self.faults.cow_copy += 1,
+            EventKind::Upgrade { .. } => self.faults.cow_upgrade += 1,
+            EventKind::SwapIn { .. } => self.faults.swap_in += 1,
+            EventKind::FileIn { .. } => self.faults.file_in += 1,
+            EventKind::Writeback { .. } => self.writebacks += 1,
+            EventKind::InvalidAccess { .. } => self.faults.invalid += 1,
+            // an evicted frame goes back on the free list
+            EventKind::Evict { .. } => {
+                self.evictions += 1;
+                self.frames_freed += 1;
+            },
+            EventKind::SwapOut { .. } => {
+                self.evictions += 1;
+                self.frames_freed += 1;
+                self.swap_outs += 1;
+            },
+            _ => {}
+        }
+    }
+
+    fn kb(&self, frames: usize) -> usize {
+        frames * self.page_size / 1024
+    }
+}
+
+// A report in the style of /proc/meminfo.
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "MemTotal:       {:>10} kB", self.kb(self.total_frames))?;
+        writeln!(f, "MemFree:        {:>10} kB", self.kb(self.free_frames))?;
+        writeln!(f, "PageTables:     {:>10} kB", self.kb(self.table_frames))?;
+        writeln!(f, "DataFrames:     {:>10} kB", self.kb(self.data_frames))?;
+        writeln!(f, "Shared:         {:>10} kB", self.kb(self.shared_frames))?;
+        writeln!(f, "Private:        {:>10} kB", self.kb(self.private_frames))?;
+        writeln!(f, "Resident:       {:>10} kB", self.kb(self.resident_pages))?;
+        writeln!(f, "Swapped:        {:>10} kB", self.kb(self.swapped_pages))?;
+        writeln!(f, "Cached:         {:>10} kB", self.kb(self.cached_frames))?;
+        writeln!(f, "Shmem:          {:>10} kB", self.kb(self.shmem_frames))?;
+        writeln!(f, "LargePages:     {:>10}", self.large_pages)?;
+        writeln!(f, "PageSize:       {:>10} B", self.page_size)?;
+        writeln!(f, "LargePageSize:  {:>10} kB", self.large_page_size / 1024)?;
+        writeln!(f, "FramesTotal:    {:>10}", self.total_frames)?;
+        writeln!(f, "FramesAlloc:    {:>10}", self.frames_allocated)?;
+        writeln!(f, "FramesFreed:    {:>10}", self.frames_freed)?;
+        writeln!(f, "Evictions:      {:>10}", self.evictions)?;
+        writeln!(f, "SwapOuts:       {:>10}", self.swap_outs)?;
+        writeln!(f, "Writebacks:     {:>10}", self.writebacks)?;
+        writeln!(f, "PgFault:        {:>10}", self.faults.total())?;
+        writeln!(f, "PgZeroFill:     {:>10}", self.faults.zero_fill)?;
+        writeln!(f, "PgCowCopy:      {:>10}", self.faults.cow_copy)?;
+        writeln!(f, "PgCowUpgrade:   {:>10}", self.faults.cow_upgrade)?;
+        writeln!(f, "PgSwapIn:       {:>10}", self.faults.swap_in)?;
+        writeln!(f, "PgFileIn:       {:>10}", self.faults.file_in)?;
+        writeln!(f, "PgInvalid:      {:>10}", self.faults.invalid)?;
+        writeln!(f, "TlbHits:        {:>10}", self.tlb.hits)?;
+        writeln!(f, "TlbMisses:      {:>10}", self.tlb.misses)?;
+        writeln!(f, "TlbHitRate:     {:>10.2} %", self.tlb.hit_rate() * 100.0)?;
+        writeln!(f, "TlbFlushes:     {:>10}", self.tlb.flushes)?;
+        writeln!(f, "TlbShootdowns:  {:>10}", self.tlb.invalidations)?;
+        writeln!(f, "TlbReach:       {:>10} kB", self.tlb_reach / 1024)?;
+        writeln!(f, "PageWalks:      {:>10}", self.walks.count)?;
+        writeln!(f, "WalkReads:      {:>10}", self.walks.reads)?;
+        writeln!(f, "WalkCost:       {:>10.2}", self.walks.average())
+    }
+}
+
+// One line of `ps`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    // none for pid 0
+    pub parent: Option<u32>,
+    pub state: ProcessState,
+    pub resident: usize,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ProcessList {
+    pub procs: Vec<ProcessInfo>,
+}
+
+// A listing in the style of ps, one process per line in pid order.
+impl fmt::Display for ProcessList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>6} {:>6} {:<9} {:>8}", "PID", "PPID", "STATE", "RSS")?;
+        for proc in &self.procs {
+            let parent = match proc.parent {
+                Some(pid) => pid.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(f, "{:>6} {:>6} {:<9} {:>8}", proc.pid, parent, proc.state, proc.resident)?;
+        }
+        Ok(())
+    }
+}
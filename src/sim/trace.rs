@@ -0,0 +1,581 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::check::{Access, Simulator};
+use super::config::{ConfigError, SimulatorConfig, Verbosity};
+use super::error::SimError;
+use super::pointer::Pointer;
+use super::stats::Faults;
+use crate::mem::mmap::Prot;
+use crate::mem::replace::{Opt, PolicyKind};
+
+// The trace formats `replay` reads.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    // `R addr [size]`, `W addr [size]` and `I addr [size]` for reads,
+    // writes and instruction fetches, `F` to fork and `S pid` to switch
+    Native,
+    // valgrind --tool=lackey --trace-mem=yes, e.g. ` S 7ff000a18,8`
+    Lackey,
+    // Dinero's din, `label addr [size]` with 0 for a read, 1 a write and
+    // 2 an instruction fetch
+    Dinero,
+}
+
+impl FromStr for TraceFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "native" | "vmem" => Ok(Self::Native),
+            "lackey" | "valgrind" => Ok(Self::Lackey),
+            "din" | "dinero" => Ok(Self::Dinero),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TraceFormat {
+    // Guesses the format from a line: the first one that reads it as a
+    // reference. Gives up on lines that could be in any of them, such as
+    // comments and din escapes, and takes a line none of them reads for
+    // native, so it is reported as a syntax error.
+    pub fn detect(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("==") {
+            return None;
+        }
+        let formats = [Self::Lackey, Self::Dinero, Self::Native];
+        if let Some(format) = formats.iter().find(|f| f.parse(line).is_some_and(|ops| !ops.is_empty())) {
+            return Some(*format);
+        }
+        if formats.iter().any(|f| f.parse(line).is_some()) {
+            None
+        } else {
+            Some(Self::Native)
+        }
+    }
+
+    // The references on one line, none for blank lines, comments and
+    // whatever else a tool prints along with its trace. Addresses are
+    // hex, with or without 0x.
+    pub fn parse(&self, line: &str) -> Option<Vec<TraceOp>> {
+        match self {
+            Self::Native => parse_native(line),
+            Self::Lackey => parse_lackey(line),
+            Self::Dinero => parse_dinero(line),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceOp {
+    Access { access: Access, addr: u64, size: u64 },
+    Fork,
+    Switch(u32),
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+fn parse_native(line: &str) -> Option<Vec<TraceOp>> {
+    let line = line.split('#').next().unwrap_or("");
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let op = match words.as_slice() {
+        [] => return Some(Vec::new()),
+        ["F"] => TraceOp::Fork,
+        ["S", pid] => TraceOp::Switch(pid.parse().ok()?),
+        [kind, addr, rest @ ..] if rest.len() <= 1 => {
+            let access = match *kind {
+                "R" => Access::Read,
+                "W" => Access::Write,
+                "I" => Access::Fetch,
+                _ => return None
+            };
+            let size = match rest.first() {
+                Some(size) => size.parse().ok()?,
+                None => 1
+            };
+            TraceOp::Access { access, addr: parse_hex(addr)?, size }
+        },
+        _ => return None
+    };
+    Some(vec![op])
+}
+
+fn parse_lackey(line: &str) -> Option<Vec<TraceOp>> {
+    // valgrind's own messages
+    if line.starts_with("==") || line.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    let mut words = line.split_whitespace();
+    let kind = words.next()?;
+    let (addr, size) = words.next()?.split_once(',')?;
+    let (addr, size) = (parse_hex(addr)?, size.parse().ok()?);
+    let at = |access| TraceOp::Access { access, addr, size };
+    match kind {
+        "I" => Some(vec![at(Access::Fetch)]),
+        "L" => Some(vec![at(Access::Read)]),
+        "S" => Some(vec![at(Access::Write)]),
+        // a modify is a load and a store to the same place
+        "M" => Some(vec![at(Access::Read), at(Access::Write)]),
+        _ => None
+    }
+}
+
+fn parse_dinero(line: &str) -> Option<Vec<TraceOp>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (label, addr, size) = match words.as_slice() {
+        [] => return Some(Vec::new()),
+        [label, addr] => (*label, *addr, 1),
+        [label, addr, size] => (*label, *addr, size.parse().ok()?),
+        _ => return None
+    };
+    let access = match label {
+        "0" => Access::Read,
+        "1" => Access::Write,
+        "2" => Access::Fetch,
+        // escapes meant for the cache simulator
+        "3" | "4" => return Some(Vec::new()),
+        _ => return None
+    };
+    Some(vec![TraceOp::Access { access, addr: parse_hex(addr)?, size }])
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    Syntax { line: usize, text: String },
+    Sim { line: usize, err: SimError },
+    Config(ConfigError),
+    // a simulator to replay the trace on could not be started
+    Start(SimError),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read trace: {}", err),
+            Self::Syntax { line, text } => write!(f, "line {}: not a trace record: \"{}\"", line, text),
+            Self::Sim { line, err } => write!(f, "line {}: {}", line, err),
+            Self::Config(err) => write!(f, "{}", err),
+            Self::Start(err) => write!(f, "could not start the simulator: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+// The faults and evictions one line of a trace caused.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LineStats {
+    pub line: usize,
+    pub faults: Faults,
+    pub evictions: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TraceReport {
+    pub lines: usize,
+    pub accesses: u64,
+    pub faults: Faults,
+    pub evictions: u64,
+    // only the lines that faulted or evicted something
+    pub per_line: Vec<LineStats>,
+}
+
+impl TraceReport {
+    // One row per line in `per_line`.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "line,zero_fill,cow_copy,cow_upgrade,swap_in,file_in,invalid,evictions")?;
+        for s in &self.per_line {
+            let f = &s.faults;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                s.line, f.zero_fill, f.cow_copy, f.cow_upgrade, f.swap_in, f.file_in, f.invalid, s.evictions
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Lines:          {:>10}", self.lines)?;
+        writeln!(f, "Accesses:       {:>10}", self.accesses)?;
+        writeln!(f, "PgFault:        {:>10}", self.faults.total())?;
+        writeln!(f, "PgZeroFill:     {:>10}", self.faults.zero_fill)?;
+        writeln!(f, "PgCowCopy:      {:>10}", self.faults.cow_copy)?;
+        writeln!(f, "PgCowUpgrade:   {:>10}", self.faults.cow_upgrade)?;
+        writeln!(f, "PgSwapIn:       {:>10}", self.faults.swap_in)?;
+        writeln!(f, "PgFileIn:       {:>10}", self.faults.file_in)?;
+        writeln!(f, "PgInvalid:      {:>10}", self.faults.invalid)?;
+        writeln!(f, "Evictions:      {:>10}", self.evictions)?;
+        writeln!(f, "FaultingLines:  {:>10}", self.per_line.len())
+    }
+}
+
+fn since(now: Faults, then: Faults) -> Faults {
+    Faults {
+        zero_fill: now.zero_fill - then.zero_fill,
+        cow_copy: now.cow_copy - then.cow_copy,
+        cow_upgrade: now.cow_upgrade - then.cow_upgrade,
+        swap_in: now.swap_in - then.swap_in,
+        file_in: now.file_in - then.file_in,
+        invalid: now.invalid - then.invalid,
+    }
+}
+
+// A whole trace read into memory, so it can be replayed on as many
+// simulators as needed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Trace {
+    lines: usize,
+    // the references on every line that has any, with its line number
+    ops: Vec<(usize, Vec<TraceOp>)>,
+}
+
+impl Trace {
+    // Reads a trace, guessing its format from the first record if none
+    // is given.
+    pub fn read<R: BufRead>(input: R, format: Option<TraceFormat>) -> Result<Self, TraceError> {
+        let mut format = format;
+        let mut trace = Trace::default();
+        for (i, line) in input.lines().enumerate() {
+            let line = line?;
+            trace.lines = i + 1;
+            if format.is_none() {
+                format = TraceFormat::detect(&line);
+            }
+            let ops = match format {
+                Some(format) => format.parse(&line)
+                    .ok_or_else(|| TraceError::Syntax { line: i + 1, text: line.clone() })?,
+                None => continue
+            };
+            if !ops.is_empty() {
+                trace.ops.push((i + 1, ops));
+            }
+        }
+        Ok(trace)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, format: Option<TraceFormat>) -> Result<Self, TraceError> {
+        let file = File::open(path)?;
+        Self::read(BufReader::new(file), format)
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    // Every operation in order, whatever line it came from.
+    pub fn ops(&self) -> impl Iterator<Item = TraceOp> + '_ {
+        self.ops.iter().flat_map(|(_, ops)| ops.iter().copied())
+    }
+}
+
+// Replays a trace against the simulator. Addresses are used as they are.
+// Every page the trace touches is mapped on first use and allows any
+// access, since the trace comes from a program that ran, and is given a
+// frame by its first reference, whether that reads or writes it.
+pub fn replay(sim: &mut Simulator, trace: &Trace) -> Result<TraceReport, TraceError> {
+    run(sim, trace, &mut |_, _| {})
+}
+
+// Replays a trace, calling `on_ref` with the page address of every
+// reference just before it is made.
+fn run(sim: &mut Simulator, trace: &Trace, on_ref: &mut dyn FnMut(&Simulator, u64)) -> Result<TraceReport, TraceError> {
+    let page_size = sim.config().get_page_size() as u64;
+    let start = sim.event_log().totals();
+    let mut report = TraceReport { lines: trace.lines, ..TraceReport::default() };
+    for (line, ops) in &trace.ops {
+        let before = sim.event_log().totals();
+        for &op in ops {
+            if let TraceOp::Access { .. } = op {
+                report.accesses += 1;
+            }
+            step(sim, op, page_size, on_ref).map_err(|err| TraceError::Sim { line: *line, err })?;
+        }
+        let after = sim.event_log().totals();
+        let faults = since(after.faults, before.faults);
+        let evictions = after.evictions - before.evictions;
+        if faults.total() > 0 || evictions > 0 {
+            report.per_line.push(LineStats { line: *line, faults, evictions });
+        }
+    }
+    let end = sim.event_log().totals();
+    report.faults = since(end.faults, start.faults);
+    report.evictions = end.evictions - start.evictions;
+    Ok(report)
+}
+
+pub(crate) fn step(sim: &mut Simulator, op: TraceOp, page_size: u64, on_ref: &mut dyn FnMut(&Simulator, u64)) -> Result<(), SimError> {
+    let (access, addr, size) = match op {
+        TraceOp::Fork => return sim.fork().map(|_| ()),
+        TraceOp::Switch(pid) => return sim.switch(pid),
+        TraceOp::Access { access, addr, size } => (access, addr, size),
+    };
+    // an access may straddle a page boundary
+    let last = addr.saturating_add(size.max(1) - 1);
+    for page in addr / page_size..=last / page_size {
+        let va = addr.max(page * page_size);
+        let ptr = Pointer::<u64>::from_addr(va, 0);
+        if !sim.mapped(ptr)? {
+            sim.register(ptr)?;
+            sim.protect(page * page_size, page_size as usize, Prot::READ_WRITE_EXEC)?;
+        }
+        on_ref(sim, page * page_size);
+        sim.touch(ptr, access)?;
+    }
+    Ok(())
+}
+
+// One policy's replay of a trace.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PolicyRun {
+    pub policy: &'static str,
+    pub report: TraceReport,
+}
+
+// How every online policy does on a trace against Belady's OPT, which
+// always comes last, with the same number of frames.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comparison {
+    pub frames: u32,
+    // what is left for pages once the zero page and page tables are in
+    pub user_frames: u32,
+    pub runs: Vec<PolicyRun>,
+}
+
+impl Comparison {
+    pub fn opt(&self) -> Option<&PolicyRun> {
+        self.runs.iter().find(|run| run.policy == "opt")
+    }
+}
+
+// Each policy's faults, how many of the references faulted, and how many
+// more faults it took than OPT.
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let best = self.opt().map(|run| run.report.faults.total()).unwrap_or(0);
+        writeln!(f, "Frames:         {:>10}", self.frames)?;
+        writeln!(f, "UserFrames:     {:>10}", self.user_frames)?;
+        writeln!(f, "{:<8} {:>10} {:>10} {:>10} {:>8} {:>8}", "Policy", "Faults", "ZeroFill", "Evictions", "PerRef", "OverOPT")?;
+        for run in &self.runs {
+            let faults = run.report.faults.total();
+            let rate = match run.report.accesses {
+                0 => 0.0,
+                refs => faults as f64 / refs as f64,
+            };
+            writeln!(
+                f,
+                "{:<8} {:>10} {:>10} {:>10} {:>8.3} {:>8}",
+                run.policy, faults, run.report.faults.zero_fill, run.report.evictions, rate, faults.saturating_sub(best)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Replays the trace on a fresh simulator for every built-in policy and
+// then for OPT, all on the machine `config` describes. The first run
+// records the reference string OPT needs.
+pub fn compare(config: &SimulatorConfig, trace: &Trace) -> Result<Comparison, TraceError> {
+    let config = config.clone().debug(Verbosity::Quiet);
+    config.validate().map_err(TraceError::Config)?;
+    let mut refs = Vec::new();
+    let mut runs = Vec::new();
+    let mut user_frames = 0;
+    for (i, &kind) in PolicyKind::ALL.iter().enumerate() {
+        let mut sim = Simulator::begin(config.clone().policy(kind)).map_err(TraceError::Start)?;
+        let report = if i == 0 {
+            let report = run(&mut sim, trace, &mut |sim, page| refs.push((sim.current(), page)))?;
+            let kept = 1 + sim.stats().table_frames as u32;
+            user_frames = config.get_frames().saturating_sub(kept);
+            report
+        } else {
+            replay(&mut sim, trace)?
+        };
+        runs.push(PolicyRun { policy: sim.machine().mem().policy().name(), report });
+    }
+
+    let mut sim = Simulator::with_policy(config.clone(), Opt::new(&refs)).map_err(TraceError::Start)?;
+    let report = replay(&mut sim, trace)?;
+    runs.push(PolicyRun { policy: "opt", report });
+    Ok(Comparison { frames: config.get_frames(), user_frames, runs })
+}
+
+// A point where a policy took more faults with more user frames than at
+// the point before it, Belady's anomaly.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Anomaly {
+    pub policy: &'static str,
+    pub frames: u32,
+    pub faults: u64,
+    pub fewer_frames: u32,
+    pub fewer_faults: u64,
+}
+
+// A comparison for every user frame count of a sweep, fewest first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Sweep {
+    pub points: Vec<Comparison>,
+}
+
+impl Sweep {
+    fn faults(&self, point: usize, run: usize) -> u64 {
+        self.points[point].runs[run].report.faults.total()
+    }
+
+    // Random's faults go up and down with its choices, so only the
+    // deterministic policies can show an anomaly.
+    fn anomalous(&self, point: usize, run: usize) -> bool {
+        point > 0 && self.points[point].runs[run].policy != "random"
+            && self.faults(point, run) > self.faults(point - 1, run)
+    }
+
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        let mut found = Vec::new();
+        for (point, comparison) in self.points.iter().enumerate() {
+            for (run, policy) in comparison.runs.iter().enumerate() {
+                if self.anomalous(point, run) {
+                    found.push(Anomaly {
+                        policy: policy.policy,
+                        frames: comparison.user_frames,
+                        faults: self.faults(point, run),
+                        fewer_frames: self.points[point - 1].user_frames,
+                        fewer_faults: self.faults(point - 1, run),
+                    });
+                }
+            }
+        }
+        found
+    }
+
+    // The fault curve of every policy, one row per point, with the
+    // anomalous points flagged.
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "policy,frames,faults,evictions,anomaly")?;
+        let policies = self.points.first().map(|p| p.runs.len()).unwrap_or(0);
+        for run in 0..policies {
+            for (point, comparison) in self.points.iter().enumerate() {
+                let policy = &comparison.runs[run];
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    policy.policy,
+                    comparison.user_frames,
+                    policy.report.faults.total(),
+                    policy.report.evictions,
+                    self.anomalous(point, run) as u8
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Faults for every policy at every frame count, with a * after each one
+// that went up from the row above.
+impl fmt::Display for Sweep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first = match self.points.first() {
+            Some(first) => first,
+            None => return Ok(())
+        };
+        write!(f, "{:>8}", "Frames")?;
+        for run in &first.runs {
+            write!(f, " {:>10}", run.policy)?;
+        }
+        writeln!(f)?;
+        for (point, comparison) in self.points.iter().enumerate() {
+            write!(f, "{:>8}", comparison.user_frames)?;
+            for run in 0..comparison.runs.len() {
+                let mark = if self.anomalous(point, run) { "*" } else { " " };
+                write!(f, " {:>9}{}", self.faults(point, run), mark)?;
+            }
+            writeln!(f)?;
+        }
+        let anomalies = self.anomalies();
+        if anomalies.is_empty() {
+            return writeln!(f, "No anomalies");
+        }
+        for a in anomalies {
+            writeln!(
+                f,
+                "Anomaly: {} takes {} faults with {} frames but {} with {}",
+                a.policy, a.faults, a.frames, a.fewer_faults, a.fewer_frames
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Compares the policies on the trace at every count of user frames in
+// `frames`, keeping the rest of `config`. The machine is given the
+// frames its zero page and page tables take on top.
+pub fn sweep<I: IntoIterator<Item = u32>>(config: &SimulatorConfig, trace: &Trace, frames: I) -> Result<Sweep, TraceError> {
+    let mut counts: Vec<u32> = frames.into_iter().collect();
+    counts.sort_unstable();
+    counts.dedup();
+    let kept = overhead(config, trace)?;
+    let mut sweep = Sweep::default();
+    for n in counts {
+        sweep.points.push(compare(&config.clone().frames(n.saturating_add(kept)), trace)?);
+    }
+    Ok(sweep)
+}
+
+// The frames a replay of the trace keeps for itself, the zero page and
+// every page directory and table, none of which are ever evicted. It is
+// replayed once on a machine big enough to keep all of it resident.
+fn overhead(config: &SimulatorConfig, trace: &Trace) -> Result<u32, TraceError> {
+    let page_size = config.get_page_size() as u64;
+    let mut pages = HashSet::new();
+    let mut procs = 1;
+    for op in trace.ops() {
+        match op {
+            TraceOp::Access { addr, size, .. } => {
+                let last = addr.saturating_add(size.max(1) - 1);
+                pages.extend(addr / page_size..=last / page_size);
+            },
+            TraceOp::Fork => procs += 1,
+            TraceOp::Switch(_) => {}
+        }
+    }
+    // each process may need a frame and a table at every level per page
+    let levels = config.geometry().levels();
+    let frames = u32::try_from(1 + procs * (pages.len() * (levels + 1) + levels)).unwrap_or(u32::MAX);
+    let config = config.clone().debug(Verbosity::Quiet).frames(frames);
+    let mut sim = Simulator::begin(config).map_err(TraceError::Start)?;
+    replay(&mut sim, trace)?;
+    Ok(1 + sim.stats().table_frames as u32)
+}
+
+// Frame counts written as `min-max` or `min-max/step`, at least one.
+pub fn parse_frames(text: &str) -> Option<Vec<u32>> {
+    let (range, step) = match text.split_once('/') {
+        Some((range, step)) => (range, step.parse().ok()?),
+        None => (text, 1),
+    };
+    let (min, max) = range.split_once('-')?;
+    let (min, max): (u32, u32) = (min.parse().ok()?, max.parse().ok()?);
+    if step == 0 || min == 0 || min > max {
+        return None;
+    }
+    Some((min..=max).step_by(step).collect())
+}
@@ -0,0 +1,38 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+mod common;
+use common::TempFile;
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-vmem"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+const SCRIPT: &str = "register x\nwrite x 1\nfork\nwrite x 2\nprint\n";
+
+#[test]
+fn runs_quietly_without_a_config() {
+    let out = run(&[], SCRIPT);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(!stdout.contains("PGZERO"));
+    assert!(!stdout.contains("PAGE DIRECTORY"));
+}
+
+#[test]
+fn takes_a_config_file_on_its_own() {
+    let conf = TempFile::with("cli", "conf", b"frames = 16\ndebug = full\n");
+    let out = run(&[conf.path().to_str().unwrap()], SCRIPT);
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("PGZERO"));
+    assert!(stdout.contains("PAGE DIRECTORY"));
+}
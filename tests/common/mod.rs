@@ -0,0 +1,51 @@
+// Not every test file uses every helper.
+#![allow(dead_code)]
+
+pub mod json;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use rust_vmem::sim::check::Simulator;
+use rust_vmem::sim::pointer::Pointer;
+
+pub const PAGE: u64 = 4096;
+
+// The first word of page `n` above the base.
+pub fn page(sim: &Simulator, n: u64) -> Pointer<u64> {
+    Pointer::from_addr(sim.config().get_base() + n * PAGE, 0)
+}
+
+// A file in the temp directory that is deleted when dropped, so a failed
+// test does not leave it behind.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(name: &str, ext: &str) -> Self {
+        Self(env::temp_dir().join(format!("vmem-{}-{}.{}", name, process::id(), ext)))
+    }
+
+    pub fn with(name: &str, ext: &str, data: &[u8]) -> Self {
+        let file = Self::new(name, ext);
+        fs::write(&file.0, data).unwrap();
+        file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
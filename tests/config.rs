@@ -0,0 +1,12 @@
+use rust_vmem::sim::check::Simulator;
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+
+#[test]
+fn begin_rejects_an_invalid_config() {
+    match Simulator::begin(SimulatorConfig::new().frames(1)) {
+        Err(SimError::InvalidConfig(_)) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("a single frame was accepted"),
+    }
+}
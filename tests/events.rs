@@ -0,0 +1,123 @@
+use std::fs;
+
+use rust_vmem::sim::check::{Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+
+mod common;
+use common::json::{self, Json};
+use common::{page, TempFile, PAGE};
+
+// Maps two pages, forks and writes one of them from the child, sampling
+// the working set as it goes.
+fn busy() -> Simulator {
+    let mut sim = Simulator::begin(SimulatorConfig::new().working_set(4, 2)).unwrap();
+    for n in 0..2 {
+        sim.register(page(&sim, n)).unwrap();
+        sim.write(page(&sim, n), ValueType::UnsignedInt(1)).unwrap();
+    }
+    sim.fork().unwrap();
+    sim.write(page(&sim, 0), ValueType::UnsignedInt(2)).unwrap();
+    sim
+}
+
+#[test]
+fn the_log_keeps_the_latest_events_and_counts_them_all() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().max_events(4)).unwrap();
+    for n in 0..8 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    }
+    let log = sim.event_log();
+    assert_eq!(log.len(), 4);
+    assert_eq!(sim.events().last().unwrap().time, log.len() as u64 + log.dropped() - 1);
+    assert_eq!(sim.stats().faults.zero_fill, 8);
+
+    let mut sim = Simulator::begin(SimulatorConfig::new().max_events(0)).unwrap();
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    assert!(sim.event_log().is_empty());
+    assert_eq!(sim.stats().faults.zero_fill, 1);
+}
+
+#[test]
+fn evicted_frames_count_as_freed() {
+    let swap = TempFile::new("freed", "swap");
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    sim.swapon(&swap, 16).unwrap();
+    for n in 0..12 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    }
+    let stats = sim.stats();
+    assert!(stats.evictions > 0);
+    // every frame but the zero page is either free or accounted for
+    let in_use = stats.frames_allocated - stats.frames_freed;
+    assert_eq!(in_use as usize, stats.total_frames - 1 - stats.free_frames);
+}
+
+#[test]
+fn a_frame_mapped_twice_is_one_shared_frame() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let base = sim.config().get_base();
+    let id = sim.shm_open("twice", PAGE as usize).unwrap();
+    sim.shm_attach(id, base).unwrap();
+    sim.shm_attach(id, base + PAGE).unwrap();
+    sim.write(page(&sim, 0), ValueType::UnsignedInt(7)).unwrap();
+    sim.write(page(&sim, 1), ValueType::UnsignedInt(8)).unwrap();
+
+    let stats = sim.process_stats(0).unwrap();
+    assert_eq!(stats.resident_pages, 2);
+    assert_eq!(stats.data_frames, 1);
+    assert_eq!(stats.shared_frames, 1);
+    assert_eq!(stats.private_frames, 0);
+}
+
+#[test]
+fn jsonl_has_one_object_per_event() {
+    let sim = busy();
+    let out = TempFile::new("events", "jsonl");
+    sim.export_events(&out).unwrap();
+    let text = fs::read_to_string(&out).unwrap();
+
+    let lines: Vec<Json> = text.lines().map(|line| json::parse(line).unwrap()).collect();
+    assert_eq!(lines.len(), sim.event_log().len());
+    for (line, event) in lines.iter().zip(sim.events()) {
+        assert_eq!(line.get("time").and_then(Json::as_u64), Some(event.time));
+        assert_eq!(line.get("pid").and_then(Json::as_u64), Some(event.pid as u64));
+        assert_eq!(line.get("event").and_then(Json::as_str), Some(event.kind.name()));
+        for (key, value) in event.kind.fields() {
+            assert_eq!(line.get(key).and_then(Json::as_u64), Some(value), "{} of {}", key, event.kind.name());
+        }
+    }
+    assert!(lines.iter().any(|line| line.get("event").and_then(Json::as_str) == Some("cow_copy")));
+}
+
+#[test]
+fn chrome_trace_is_one_json_document() {
+    let sim = busy();
+    let out = TempFile::new("events", "json");
+    sim.export_chrome_trace(&out).unwrap();
+    let trace = json::parse(&fs::read_to_string(&out).unwrap()).unwrap();
+
+    let events = trace.get("traceEvents").and_then(Json::as_array).unwrap();
+    assert_eq!(events.len(), sim.event_log().len());
+    for (entry, event) in events.iter().zip(sim.events()) {
+        assert_eq!(entry.get("name").and_then(Json::as_str), Some(event.kind.name()));
+        assert_eq!(entry.get("ts").and_then(Json::as_u64), Some(event.time));
+        assert_eq!(entry.get("pid").and_then(Json::as_u64), Some(event.pid as u64));
+        let phase = entry.get("ph").and_then(Json::as_str).unwrap();
+        if event.kind.name() == "working_set" {
+            assert_eq!(phase, "C");
+        } else {
+            assert_eq!(phase, "i");
+        }
+        let args = entry.get("args").unwrap();
+        for (key, value) in event.kind.fields() {
+            assert_eq!(args.get(key).and_then(Json::as_u64), Some(value));
+        }
+    }
+    assert!(events.iter().any(|entry| entry.get("ph").and_then(Json::as_str) == Some("C")));
+}
@@ -0,0 +1,69 @@
+use rust_vmem::mem::mmap::Prot;
+use rust_vmem::proc::image::Image;
+use rust_vmem::proc::proc::ProcessState;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType, EXEC_FAILED};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+fn word(sim: &mut Simulator, addr: u64) -> u64 {
+    match sim.read(Pointer::<u64>::from_addr(addr, 0), DataType::UnsignedInt).unwrap() {
+        ValueType::UnsignedInt(value) => value as u64,
+        other => panic!("read {:?}", other),
+    }
+}
+
+#[test]
+fn exec_loads_data_in_eight_byte_words() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(32)).unwrap();
+    let data: Vec<u8> = (1..=20).collect();
+    let image = Image::new()
+        .segment(0x10000, 0x1000, Prot::READ_WRITE, &data)
+        .stack(0x800000, 0x4000)
+        .arg("prog");
+    sim.fork().unwrap();
+    let sp = sim.exec(&image).unwrap().unwrap();
+
+    assert_eq!(word(&mut sim, 0x10000), u64::from_le_bytes([1, 2, 3, 4, 5, 6, 7, 8]));
+    assert_eq!(word(&mut sim, 0x10008), u64::from_le_bytes([9, 10, 11, 12, 13, 14, 15, 16]));
+    assert_eq!(word(&mut sim, 0x10010), u64::from_le_bytes([17, 18, 19, 20, 0, 0, 0, 0]));
+    // argc, then argv[0] pointing at the string above the pointers
+    assert_eq!(word(&mut sim, sp), 1);
+    assert_eq!(word(&mut sim, sp + 8), sp + 4 * 8);
+    assert_eq!(word(&mut sim, sp + 16), 0);
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn exec_loads_high_words_whole() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(32)).unwrap();
+    let data = 0xfedc_ba98_7654_3210u64.to_le_bytes();
+    let image = Image::new().segment(0x10000, 0x1000, Prot::READ, &data);
+    sim.fork().unwrap();
+    sim.exec(&image).unwrap();
+    assert_eq!(word(&mut sim, 0x10000), 0xfedc_ba98_7654_3210);
+}
+
+#[test]
+fn exec_that_runs_out_of_memory_kills_the_process() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    // every page of the segment needs a frame of its own
+    let data = vec![1u8; 16 * 4096];
+    let image = Image::new().segment(0x10000, data.len() as u64, Prot::READ, &data);
+    let child = sim.fork().unwrap();
+    assert_eq!(sim.exec(&image), Err(SimError::OutOfMemory));
+
+    assert_eq!(sim.current(), 0);
+    let info = sim.ps().procs.into_iter().find(|p| p.pid == child).unwrap();
+    assert_eq!(info.state, ProcessState::Zombie);
+    assert_eq!(sim.wait().unwrap(), Some((child, EXEC_FAILED)));
+}
+
+#[test]
+fn a_bad_image_leaves_the_process_alone() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(16)).unwrap();
+    let image = Image::new().stack(0x1000, 0x2000);
+    assert_eq!(image.stack_range(), None);
+    assert!(matches!(sim.exec(&image), Err(SimError::InvalidArgument(_))));
+    assert_eq!(sim.ps().procs[0].state, ProcessState::Running);
+}
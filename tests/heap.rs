@@ -0,0 +1,71 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use rust_vmem::mem::mmap::{Prot, Sharing};
+use rust_vmem::mem::ptable::PagingMode;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::{SimulatorConfig, HEAP_OFFSET};
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+mod common;
+use common::{TempFile, PAGE};
+
+#[test]
+fn sbrk_and_brk_move_the_break() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let (start, brk) = sim.heap().unwrap();
+    assert_eq!(start, brk);
+    assert_eq!(sim.sbrk(2 * PAGE as i64).unwrap(), start);
+
+    let word = Pointer::<u64>::from_addr(start + PAGE, 0);
+    sim.write(word, ValueType::UnsignedInt(7)).unwrap();
+    assert_eq!(sim.read(word, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(7));
+
+    sim.brk(start + PAGE).unwrap();
+    assert_eq!(sim.heap().unwrap(), (start, start + PAGE));
+    assert!(sim.read(word, DataType::UnsignedInt).is_err());
+}
+
+#[test]
+fn the_default_heap_base_must_be_canonical() {
+    let config = SimulatorConfig::new().paging(PagingMode::Bits32).base(0xf000_0000);
+    match Simulator::begin(config) {
+        Err(SimError::InvalidConfig(_)) => {},
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("a heap past 4 GiB was accepted"),
+    }
+    let config = SimulatorConfig::new().paging(PagingMode::Bits32).base(0xf000_0000).heap_base(0x4000_0000);
+    assert!(Simulator::begin(config).is_ok());
+}
+
+#[test]
+fn mappings_stay_out_of_the_heap() {
+    let path = TempFile::with("heap", "bin", &[0; 2 * PAGE as usize]);
+    let config = SimulatorConfig::new();
+    let heap_base = config.get_base() + PAGE;
+    let mut sim = Simulator::begin(config.heap_base(heap_base)).unwrap();
+    let addr = sim.mmap_file(&path, 0, 2 * PAGE as usize, Prot::READ_WRITE, Sharing::Private).unwrap();
+    assert!(addr + 2 * PAGE <= heap_base || addr >= heap_base + HEAP_OFFSET);
+
+    sim.sbrk(PAGE as i64).unwrap();
+    let word = Pointer::<u64>::from_addr(heap_base, 0);
+    sim.write(word, ValueType::UnsignedInt(1)).unwrap();
+    assert_eq!(sim.read(Pointer::<u64>::from_addr(addr, 0), DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(0));
+}
+
+#[test]
+fn the_brk_command_takes_an_address() {
+    let heap_base = SimulatorConfig::new().get_heap_base();
+    let script = format!("brk 0x{:x}\nbrk\n", heap_base + 2 * PAGE);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-vmem"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success());
+    let line = format!("heap 0x{:x}, break 0x{:x}", heap_base, heap_base + 2 * PAGE);
+    assert_eq!(String::from_utf8(out.stdout).unwrap().matches(&line).count(), 2);
+}
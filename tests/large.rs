@@ -0,0 +1,98 @@
+use rust_vmem::mem::ptable::PagingMode;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+mod common;
+use common::{TempFile, PAGE};
+
+// 2 MiB with four-level paging
+const LARGE: u64 = 512 * PAGE;
+
+fn start(frames: u32) -> Simulator {
+    Simulator::begin(SimulatorConfig::new().frames(frames).paging(PagingMode::Bits48)).unwrap()
+}
+
+fn at(sim: &Simulator, offset: u64) -> Pointer<u64> {
+    Pointer::from_addr(sim.config().get_base() + offset, 0)
+}
+
+#[test]
+fn a_large_page_maps_its_whole_span() {
+    let mut sim = start(2048);
+    sim.register_large(at(&sim, 8)).unwrap();
+    assert!(sim.mapped(at(&sim, 0)).unwrap());
+    assert!(sim.mapped(at(&sim, LARGE - 8)).unwrap());
+    assert!(!sim.mapped(at(&sim, LARGE)).unwrap());
+
+    let free = sim.machine().mem().free_frames();
+    sim.write(at(&sim, LARGE / 2), ValueType::UnsignedInt(5)).unwrap();
+    assert_eq!(free - sim.machine().mem().free_frames(), 512);
+    sim.write(at(&sim, LARGE - 8), ValueType::UnsignedInt(6)).unwrap();
+    assert_eq!(sim.read(at(&sim, LARGE / 2), DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(5));
+    assert_eq!(sim.read(at(&sim, 0), DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(0));
+
+    // a variable registered inside it shares it rather than mapping a page
+    sim.register(at(&sim, LARGE - PAGE)).unwrap();
+    let stats = sim.stats();
+    assert_eq!(stats.large_pages, 1);
+    assert_eq!(stats.large_page_size as u64, LARGE);
+}
+
+#[test]
+fn one_entry_covers_a_large_page() {
+    let mut sim = start(2048);
+    sim.register_large(at(&sim, 0)).unwrap();
+    sim.write(at(&sim, 0), ValueType::UnsignedInt(1)).unwrap();
+    assert!(sim.stats().tlb_reach as u64 >= LARGE);
+
+    let misses = sim.stats().tlb.misses;
+    for n in 0..512 {
+        sim.read(at(&sim, n * PAGE), DataType::UnsignedInt).unwrap();
+    }
+    assert_eq!(sim.stats().tlb.misses, misses);
+}
+
+#[test]
+fn large_pages_run_out_of_aligned_runs() {
+    // the first run holds the zero page, which leaves three
+    let mut sim = start(2048);
+    let mut result = Ok(());
+    let mut mapped = 0;
+    for n in 0..4 {
+        let ptr = at(&sim, n * LARGE);
+        sim.register_large(ptr).unwrap();
+        result = sim.write(ptr, ValueType::UnsignedInt(1));
+        if result.is_err() {
+            break;
+        }
+        mapped += 1;
+    }
+    assert_eq!(result, Err(SimError::OutOfMemory));
+    assert_eq!(mapped, 3);
+}
+
+#[test]
+fn a_run_is_cleared_by_swapping_its_pages_out() {
+    let swap = TempFile::new("large", "swap");
+    let mut sim = start(2048);
+    sim.swapon(&swap, 2048).unwrap();
+    // small pages fill every frame, so some run has to be cleared. They
+    // are all registered first, so their page tables sit together and
+    // leave the other runs to user pages.
+    let small = 4 * LARGE;
+    for n in 0..2100 {
+        sim.register(at(&sim, small + n * PAGE)).unwrap();
+    }
+    for n in 0..2100 {
+        sim.write(at(&sim, small + n * PAGE), ValueType::UnsignedInt(n as usize)).unwrap();
+    }
+    sim.register_large(at(&sim, 0)).unwrap();
+    sim.write(at(&sim, 0), ValueType::UnsignedInt(1)).unwrap();
+    assert!(sim.stats().swap_outs > 0);
+    for n in (0..2100).step_by(97) {
+        let value = sim.read(at(&sim, small + n * PAGE), DataType::UnsignedInt).unwrap();
+        assert_eq!(value, ValueType::UnsignedInt(n as usize));
+    }
+}
@@ -0,0 +1,69 @@
+use std::thread;
+
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::pointer::Pointer;
+
+// Writes `count` values and reads them all back, returning what was read
+// and the frames left free.
+fn fill(count: usize, seed: usize) -> (Vec<ValueType>, usize) {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(16)).unwrap();
+    let ptrs: Vec<_> = (0..count).map(|_| sim.pointer_from::<u64>(0)).collect();
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(seed + i)).unwrap();
+    }
+    let values = ptrs.iter().map(|&ptr| sim.read(ptr, DataType::UnsignedInt).unwrap()).collect();
+    (values, sim.machine().mem().free_frames())
+}
+
+#[test]
+fn simulators_on_separate_threads_do_not_share_memory() {
+    let handles: Vec<_> = (0..4)
+        .map(|t| thread::spawn(move || fill(4, t * 100)))
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    for (t, (values, free)) in results.iter().enumerate() {
+        let expected: Vec<_> = (0..4).map(|i| ValueType::UnsignedInt(t * 100 + i)).collect();
+        assert_eq!(values, &expected);
+        assert_eq!(*free, results[0].1);
+    }
+}
+
+#[test]
+fn simulators_hand_out_addresses_on_their_own() {
+    let mut a = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let mut b = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let first = a.pointer_from::<u64>(0);
+    a.pointer_from::<u64>(0);
+    assert_eq!(b.pointer_from::<u64>(0).vaddr(), first.vaddr());
+
+    a.register(first).unwrap();
+    a.write(first, ValueType::UnsignedInt(1)).unwrap();
+    assert!(!b.mapped(first).unwrap());
+    drop(a);
+    b.register(first).unwrap();
+    assert_eq!(b.read(first, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(0));
+}
+
+#[test]
+fn forked_processes_write_to_their_own_copies() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let ptr = sim.pointer_from::<u64>(0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+
+    let child = sim.fork().unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(2)).unwrap();
+    sim.switch(0).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(1));
+    sim.write(ptr, ValueType::UnsignedInt(3)).unwrap();
+    sim.switch(child).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(2));
+
+    // a page mapped after the fork is the child's alone
+    let late = Pointer::<u64>::from_addr(ptr.vaddr() + 4096, 0);
+    sim.register(late).unwrap();
+    sim.switch(0).unwrap();
+    assert!(!sim.mapped(late).unwrap());
+}
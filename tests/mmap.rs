@@ -0,0 +1,65 @@
+use std::fs;
+
+use rust_vmem::mem::mmap::{Prot, Sharing};
+use rust_vmem::mem::ptable::PagingMode;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+mod common;
+use common::{TempFile, PAGE};
+
+#[test]
+fn shared_writes_reach_the_file() {
+    let path = TempFile::with("shared", "bin", &[0; 2 * PAGE as usize]);
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let addr = sim.mmap_file(&path, 0, 2 * PAGE as usize, Prot::READ_WRITE, Sharing::Shared).unwrap();
+    let word = Pointer::<u64>::from_addr(addr + PAGE + 8, 0);
+    sim.write(word, ValueType::UnsignedInt(0x0102)).unwrap();
+    sim.msync(addr, 2 * PAGE as usize).unwrap();
+
+    let data = fs::read(&path).unwrap();
+    assert_eq!(&data[PAGE as usize + 8..PAGE as usize + 10], &[0x02, 0x01]);
+    assert_eq!(sim.read(word, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(0x0102));
+}
+
+#[test]
+fn exit_writes_back_shared_pages() {
+    let path = TempFile::with("exit", "bin", &[0; PAGE as usize]);
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    sim.fork().unwrap();
+    let addr = sim.mmap_file(&path, 0, PAGE as usize, Prot::READ_WRITE, Sharing::Shared).unwrap();
+    sim.write(Pointer::<u64>::from_addr(addr, 0), ValueType::UnsignedInt(9)).unwrap();
+    sim.exit(0).unwrap();
+
+    assert_eq!(fs::read(&path).unwrap()[0], 9);
+    assert!(sim.events().all(|e| e.kind.name() != "writeback_failed"));
+}
+
+#[test]
+fn a_failed_attach_drops_its_reference() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let id = sim.shm_open("edge", 2 * PAGE as usize).unwrap();
+    // the second page lies past the end of a 32-bit address space
+    let err = sim.shm_attach(id, (1 << 32) - PAGE).unwrap_err();
+    assert_eq!(err, SimError::NonCanonical(1 << 32));
+    assert_eq!(sim.stats().resident_pages, 0);
+
+    // the handle from shm_open was the last reference
+    sim.shm_close(id).unwrap();
+    assert_eq!(sim.machine().mem().segment_size(id), None);
+}
+
+#[test]
+fn a_mapping_past_the_top_of_memory_is_refused() {
+    let config = SimulatorConfig::new().paging(PagingMode::Bits48);
+    let mut sim = Simulator::begin(config).unwrap();
+    let id = sim.shm_open("top", 2 * PAGE as usize).unwrap();
+    let top = 0u64.wrapping_sub(PAGE);
+    assert_eq!(sim.shm_attach(id, top), Err(SimError::InvalidAddress(top)));
+    assert_eq!(sim.stats().resident_pages, 0);
+
+    sim.shm_close(id).unwrap();
+    assert_eq!(sim.machine().mem().segment_size(id), None);
+}
@@ -0,0 +1,75 @@
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+const PAGE: u64 = 4096;
+
+fn at(sim: &Simulator, offset: u64) -> Pointer<u64> {
+    Pointer::from_addr(sim.config().get_base() + offset, 0)
+}
+
+#[test]
+fn a_page_stays_until_its_last_variable_goes() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let (x, y) = (at(&sim, 0), at(&sim, 8));
+    sim.register(x).unwrap();
+    sim.register(y).unwrap();
+    assert_eq!(sim.register(y), Err(SimError::AlreadyMapped(y.vaddr())));
+    sim.write(y, ValueType::UnsignedInt(6)).unwrap();
+
+    sim.unregister(x).unwrap();
+    assert_eq!(sim.read(y, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(6));
+    assert_eq!(sim.unregister(x), Err(SimError::InvalidAddress(x.vaddr())));
+
+    sim.unregister(y).unwrap();
+    assert_eq!(sim.read(y, DataType::UnsignedInt), Err(SimError::InvalidAddress(y.vaddr())));
+}
+
+#[test]
+fn a_page_mapped_otherwise_is_not_a_variable() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let id = sim.shm_open("vars", PAGE as usize).unwrap();
+    let base = sim.config().get_base();
+    sim.shm_attach(id, base).unwrap();
+    let x = at(&sim, 0);
+    assert_eq!(sim.register(x), Err(SimError::AlreadyMapped(x.vaddr())));
+    assert_eq!(sim.unregister(x), Err(SimError::InvalidAddress(x.vaddr())));
+}
+
+#[test]
+fn munmap_frees_frames_and_empty_tables() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let before = sim.stats();
+    // two pages a page table apart, so each needs a table of its own
+    let far = 1024 * PAGE;
+    for offset in [0, far] {
+        sim.register(at(&sim, offset)).unwrap();
+        sim.write(at(&sim, offset), ValueType::UnsignedInt(1)).unwrap();
+    }
+    assert_eq!(sim.stats().table_frames, before.table_frames + 2);
+
+    sim.munmap(sim.config().get_base(), (far + PAGE) as usize).unwrap();
+    let after = sim.stats();
+    assert_eq!(after.table_frames, before.table_frames);
+    assert_eq!(after.free_frames, before.free_frames);
+    assert_eq!(after.resident_pages, 0);
+    assert_eq!(sim.read(at(&sim, far), DataType::UnsignedInt), Err(SimError::InvalidAddress(sim.config().get_base() + far)));
+}
+
+#[test]
+fn munmap_refuses_a_locked_page() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    let x = at(&sim, 0);
+    sim.register(x).unwrap();
+    sim.write(x, ValueType::UnsignedInt(3)).unwrap();
+    sim.lock(x).unwrap();
+    let base = sim.config().get_base();
+    assert!(matches!(sim.munmap(base, PAGE as usize), Err(SimError::InvalidArgument(_))));
+    assert!(matches!(sim.unregister(x), Err(SimError::InvalidArgument(_))));
+    assert_eq!(sim.read(x, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(3));
+
+    sim.unlock(x).unwrap();
+    sim.unregister(x).unwrap();
+    assert_eq!(sim.stats().resident_pages, 0);
+}
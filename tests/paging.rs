@@ -0,0 +1,84 @@
+use rust_vmem::mem::ptable::{Geometry, PagingMode};
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+
+mod common;
+use common::PAGE;
+
+fn start(paging: PagingMode) -> Simulator {
+    Simulator::begin(SimulatorConfig::new().frames(64).paging(paging)).unwrap()
+}
+
+// Frames taken by writing one word at `va`.
+fn frames_for(sim: &mut Simulator, va: u64) -> usize {
+    let before = sim.machine().mem().free_frames();
+    let ptr = Pointer::<u64>::from_addr(va, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(va as usize)).unwrap();
+    before - sim.machine().mem().free_frames()
+}
+
+#[test]
+fn each_mode_has_its_levels() {
+    for (mode, levels) in [(PagingMode::Bits32, 2), (PagingMode::Bits48, 4), (PagingMode::Bits57, 5)] {
+        let geo = Geometry::new(PAGE as usize, 0, mode);
+        assert_eq!(geo.levels(), levels);
+        assert_eq!(geo.shift(levels - 1), 12);
+    }
+    let geo = Geometry::new(PAGE as usize, 0, PagingMode::Bits57);
+    assert_eq!(geo.shift(0), 48);
+    assert_eq!(geo.index(0x00ab_0000_0000_0000, 0), 0xab);
+}
+
+#[test]
+fn canonical_addresses_sign_extend_the_top_bit() {
+    let four = Geometry::new(PAGE as usize, 0, PagingMode::Bits48);
+    let five = Geometry::new(PAGE as usize, 0, PagingMode::Bits57);
+    assert!(four.canonical(0x0000_7fff_ffff_f000));
+    assert!(four.canonical(0xffff_8000_0000_0000));
+    assert!(!four.canonical(0x0000_8000_0000_0000));
+    assert!(five.canonical(0x0000_8000_0000_0000));
+    assert!(five.canonical(0x00ff_ffff_ffff_f000));
+    assert!(five.canonical(0xff00_0000_0000_0000));
+    assert!(!five.canonical(0x0100_0000_0000_0000));
+    assert!(!five.canonical(0xfe00_0000_0000_0000));
+}
+
+#[test]
+fn five_level_walks_reach_the_whole_space() {
+    let mut sim = start(PagingMode::Bits57);
+    let high = 0x00f0_0000_0000_0000;
+    // four tables below the directory and the page itself
+    assert_eq!(frames_for(&mut sim, high), 5);
+    // another top-level entry needs its own tables
+    assert_eq!(frames_for(&mut sim, 0x0010_0000_0000_0000), 5);
+    // a neighbouring page shares all of them
+    assert_eq!(frames_for(&mut sim, high + PAGE), 1);
+
+    let top = Pointer::<u64>::from_addr(0xff00_0000_0000_0000, 0);
+    sim.register(top).unwrap();
+    sim.write(top, ValueType::UnsignedInt(7)).unwrap();
+    let ptr = Pointer::<u64>::from_addr(high, 0);
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(high as usize));
+    assert_eq!(sim.read(top, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(7));
+
+    let mut four = start(PagingMode::Bits48);
+    assert_eq!(frames_for(&mut four, 0x0000_7f00_0000_0000), 4);
+}
+
+#[test]
+fn non_canonical_addresses_are_refused() {
+    let va = 0x0000_8000_0000_0000;
+    let mut sim = start(PagingMode::Bits48);
+    let ptr = Pointer::<u64>::from_addr(va, 0);
+    assert_eq!(sim.register(ptr), Err(SimError::NonCanonical(va)));
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt), Err(SimError::NonCanonical(va)));
+
+    let va = 0x0100_0000_0000_0000;
+    let mut sim = start(PagingMode::Bits57);
+    let ptr = Pointer::<u64>::from_addr(va, 0);
+    assert_eq!(sim.register(ptr), Err(SimError::NonCanonical(va)));
+    assert_eq!(sim.write(ptr, ValueType::UnsignedInt(1)), Err(SimError::NonCanonical(va)));
+}
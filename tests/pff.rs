@@ -0,0 +1,106 @@
+use rust_vmem::mem::mmap::{Prot, Sharing};
+use rust_vmem::mem::replace::PolicyKind;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::event::EventKind;
+use rust_vmem::sim::pointer::Pointer;
+
+mod common;
+use common::{page, TempFile, PAGE};
+
+fn pff() -> SimulatorConfig {
+    SimulatorConfig::new().frames(64).working_set(100, 20).pff(5, 50)
+}
+
+// the counts the allocator keeps match the pages each process maps
+fn check_resident(sim: &Simulator) {
+    for info in sim.ps().procs {
+        assert_eq!(sim.machine().mem().resident_pages(info.pid), info.resident, "pid {}", info.pid);
+    }
+}
+
+#[test]
+fn shrinking_without_swap_keeps_every_page() {
+    let mut sim = Simulator::begin(pff()).unwrap();
+    for n in 0..16 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(n as usize + 1)).unwrap();
+    }
+    // a long run on one page keeps the fault rate low
+    for _ in 0..2000 {
+        sim.read(page(&sim, 0), DataType::UnsignedInt).unwrap();
+    }
+    for n in 0..16 {
+        assert_eq!(sim.read(page(&sim, n), DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(n as usize + 1));
+    }
+    assert_eq!(sim.stats().evictions + sim.stats().swap_outs, 0);
+    check_resident(&sim);
+}
+
+#[test]
+fn resident_counts_follow_forks_swapping_and_unmaps() {
+    let swap = TempFile::new("pff", "swap");
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(12)).unwrap();
+    sim.swapon(&swap, 32).unwrap();
+    for n in 0..6 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(n as usize)).unwrap();
+    }
+    check_resident(&sim);
+    sim.fork().unwrap();
+    check_resident(&sim);
+    for n in 6..14 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(n as usize)).unwrap();
+    }
+    assert!(sim.stats().swap_outs > 0);
+    check_resident(&sim);
+    for n in 0..6 {
+        sim.read(page(&sim, n), DataType::UnsignedInt).unwrap();
+    }
+    check_resident(&sim);
+    sim.munmap(page(&sim, 0).vaddr(), 4 * PAGE as usize).unwrap();
+    check_resident(&sim);
+    sim.exit(0).unwrap();
+    check_resident(&sim);
+}
+
+// The pages of a read-only file mapping read in while WSClock, with a
+// window of three references, runs on three user frames. `idle` reads
+// of a page still on the zero page come between the file reads.
+fn wsclock_file_ins(idle: usize) -> Vec<u64> {
+    let file = TempFile::with("wsclock", "bin", &[1; 8 * PAGE as usize]);
+    let config = SimulatorConfig::new().frames(6).policy(PolicyKind::WsClock).working_set(3, 1000);
+    let mut sim = Simulator::begin(config).unwrap();
+    let addr = sim.mmap_file(&file, 0, 8 * PAGE as usize, Prot::READ, Sharing::Private).unwrap();
+    let zero = Pointer::<u64>::from_addr(addr + 64 * PAGE, 0);
+    sim.register(zero).unwrap();
+    for n in [0, 1, 2, 3, 4, 1, 2] {
+        if n == 3 || n == 4 {
+            for _ in 0..idle {
+                sim.read(zero, DataType::UnsignedInt).unwrap();
+            }
+        }
+        sim.read(Pointer::<u64>::from_addr(addr + n * PAGE, 0), DataType::UnsignedInt).unwrap();
+    }
+    sim.events()
+        .filter_map(|e| match e.kind {
+            EventKind::FileIn { va, .. } => Some((va - addr) / PAGE),
+            _ => None
+        })
+        .collect()
+}
+
+#[test]
+fn wsclock_ages_pages_by_every_reference() {
+    // with nothing in between, no page outlives the window, so the
+    // oldest goes each time
+    assert_eq!(wsclock_file_ins(0), [0, 1, 2, 3, 4, 2]);
+    // reads of the zero page take no frame but are still references, so
+    // the pages idle through them leave the working set and the hand
+    // takes the first it finds
+    assert_eq!(wsclock_file_ins(4), [0, 1, 2, 3, 4, 1, 2]);
+}
@@ -0,0 +1,146 @@
+use rust_vmem::mem::alloc::Page;
+use rust_vmem::mem::mmap::Prot;
+use rust_vmem::mem::ptable::{Flag, Physical, Virtual};
+use rust_vmem::proc::proc::{Process, ProcessState};
+use rust_vmem::sim::check::{Simulator, ValueType};
+use rust_vmem::sim::config::{SimulatorConfig, Verbosity};
+use rust_vmem::sim::machine::Machine;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::pointer::Pointer;
+use rust_vmem::sim::script::Interpreter;
+
+fn start() -> Simulator {
+    Simulator::begin(SimulatorConfig::new().frames(16)).unwrap()
+}
+
+#[test]
+fn pid_zero_can_be_killed() {
+    let mut sim = start();
+    let ptr: Pointer<u64> = Pointer::from_addr(sim.config().get_base(), 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    sim.kill().unwrap();
+
+    let zero = &sim.ps().procs[0];
+    assert_eq!(zero.state, ProcessState::Zombie);
+    assert_eq!(zero.resident, 0);
+    assert_eq!(sim.write(ptr, ValueType::UnsignedInt(2)), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.kill(), Err(SimError::NotRunning(0)));
+}
+
+#[test]
+fn a_killed_process_cannot_fork_or_map() {
+    let mut sim = start();
+    let ptr: Pointer<u64> = Pointer::from_addr(sim.config().get_base(), 0);
+    let next: Pointer<u64> = Pointer::from_addr(ptr.vaddr() + 4096, 0);
+    sim.register(ptr).unwrap();
+    sim.kill().unwrap();
+
+    assert_eq!(sim.fork(), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.register(next), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.register_large(next), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.unregister(ptr), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.protect(ptr.vaddr(), 4096, Prot::READ), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.munmap(ptr.vaddr(), 4096), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.lock(ptr), Err(SimError::NotRunning(0)));
+    assert_eq!(sim.mapped(ptr), Err(SimError::NotRunning(0)));
+}
+
+#[test]
+fn a_script_that_forks_after_kill_carries_on() {
+    let mut interp = Interpreter::new(start());
+    interp.exec("register x").unwrap();
+    interp.exec("kill").unwrap();
+    assert!(interp.exec("fork").is_err());
+    assert!(interp.exec("register y").is_err());
+    assert!(!interp.finished());
+}
+
+#[test]
+fn pid_zero_still_cannot_exit() {
+    let mut sim = start();
+    assert!(matches!(sim.exit(0), Err(SimError::InvalidArgument(_))));
+}
+
+#[test]
+fn an_orphan_exits_once_pid_zero_is_gone() {
+    let mut sim = start();
+    let child = sim.fork().unwrap();
+    sim.switch(0).unwrap();
+    sim.kill().unwrap();
+    sim.switch(child).unwrap();
+    sim.exit(3).unwrap();
+    assert_eq!(sim.current(), child);
+    assert!(sim.ps().procs.iter().all(|p| p.state == ProcessState::Zombie));
+}
+
+#[test]
+fn only_quit_stops_a_script() {
+    let mut interp = Interpreter::new(start());
+    assert!(interp.exec("exit").is_err());
+    assert!(!interp.finished());
+    interp.exec("quit").unwrap();
+    assert!(interp.finished());
+}
+
+#[test]
+fn a_sleeping_process_cannot_map_protect_or_fork() {
+    let config = SimulatorConfig::new().frames(16);
+    let mut machine = Machine::new(&config, config.get_policy().build(None, 0));
+    let mem = machine.mem_mut();
+    let mut proc = Process::new(mem, 1, config.geometry(), Verbosity::Quiet).unwrap();
+    let base = config.get_base();
+    let va = Virtual::new(base, 0);
+    let pa = Physical::new(0, mem.zero_page() as *const Page as usize);
+    let flags = [Flag::User, Flag::Zero, Flag::CopyOnWrite];
+
+    assert_eq!(proc.state(), ProcessState::Sleeping);
+    assert_eq!(proc.map(mem, va, pa, &flags), Err(SimError::NotRunning(1)));
+    assert_eq!(proc.map_zero(mem, base, 4096), Err(SimError::NotRunning(1)));
+    assert!(matches!(proc.copy(mem, 2, Verbosity::Quiet), Err(SimError::NotRunning(1))));
+
+    proc.wake_up();
+    proc.map(mem, va, pa, &flags).unwrap();
+    proc.yieldk();
+    assert_eq!(proc.protect(mem, base, 4096, Prot::READ), Err(SimError::NotRunning(1)));
+    assert_eq!(proc.munmap(mem, base, 4096), Err(SimError::NotRunning(1)));
+    assert!(proc.mapped(mem, va));
+}
+
+#[test]
+fn pids_are_not_reused_after_wait() {
+    let mut sim = start();
+    let first = sim.fork().unwrap();
+    sim.exit(0).unwrap();
+    assert_eq!(sim.wait(), Ok(Some((first, 0))));
+    let second = sim.fork().unwrap();
+    assert!(second > first);
+    assert_eq!(sim.switch(first), Err(SimError::NoSuchProcess(first)));
+}
+
+#[test]
+fn switching_to_an_unknown_pid_stays_put() {
+    let mut sim = start();
+    assert_eq!(sim.switch(7), Err(SimError::NoSuchProcess(7)));
+    assert_eq!(sim.current(), 0);
+    assert_eq!(sim.ps().procs[0].state, ProcessState::Running);
+}
+
+#[test]
+fn ps_lines_up_its_columns() {
+    let mut sim = start();
+    sim.fork().unwrap();
+    sim.fork().unwrap();
+    sim.exit(1).unwrap();
+    let listing = sim.ps().to_string();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 4);
+    // every state is padded, so RSS ends in the same column on every line
+    assert!(lines.iter().all(|line| line.len() == lines[0].len()), "{}", listing);
+    let rss = lines[0].find("RSS").unwrap();
+    for state in &["running", "sleeping", "zombie"] {
+        let line = lines.iter().find(|line| line.contains(state)).unwrap();
+        assert_eq!(line.find(state), lines[0].find("STATE"));
+        assert_eq!(line[rss..].trim(), "0");
+    }
+}
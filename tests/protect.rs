@@ -0,0 +1,130 @@
+use rust_vmem::mem::mmap::Prot;
+use rust_vmem::mem::ptable::PagingMode;
+use rust_vmem::sim::check::{Access, DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::trace::{self, Trace, TraceError, TraceFormat};
+
+mod common;
+use common::{page, PAGE};
+
+fn start(paging: PagingMode) -> Simulator {
+    Simulator::begin(SimulatorConfig::new().frames(16).paging(paging)).unwrap()
+}
+
+#[test]
+fn read_only_pages_refuse_writes() {
+    let mut sim = start(PagingMode::Bits32);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(7)).unwrap();
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ).unwrap();
+    assert_eq!(sim.write(ptr, ValueType::UnsignedInt(8)), Err(SimError::ProtectionFault(ptr.vaddr())));
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(7));
+}
+
+#[test]
+fn kernel_flags_survive_in_every_paging_mode() {
+    for paging in [PagingMode::Bits32, PagingMode::Bits48] {
+        let mut sim = start(paging);
+        let ptr = page(&sim, 0);
+        sim.register(ptr).unwrap();
+        // registered pages are not executable
+        assert_eq!(sim.fetch(ptr), Err(SimError::ProtectionFault(ptr.vaddr())));
+        sim.protect(ptr.vaddr(), PAGE as usize, Prot::NONE).unwrap();
+        assert_eq!(sim.read(ptr, DataType::UnsignedInt), Err(SimError::ProtectionFault(ptr.vaddr())));
+        sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ_EXEC).unwrap();
+        assert_eq!(sim.fetch(ptr).unwrap(), ValueType::UnsignedInt(0));
+    }
+}
+
+#[test]
+fn write_implies_read() {
+    let mut sim = start(PagingMode::Bits32);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    let write_only = Prot { read: false, write: true, exec: false };
+    sim.protect(ptr.vaddr(), PAGE as usize, write_only).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(3)).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(3));
+    assert_eq!("-w-".parse::<Prot>(), Ok(write_only));
+}
+
+#[test]
+fn fork_copies_on_write() {
+    let mut sim = start(PagingMode::Bits32);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    let child = sim.fork().unwrap();
+    sim.switch(child).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(2)).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(2));
+    sim.switch(0).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(1));
+    assert_eq!(sim.events().filter(|e| e.kind.name() == "cow_copy").count(), 1);
+}
+
+#[test]
+fn protect_after_fork_keeps_the_frame_shared() {
+    let mut sim = start(PagingMode::Bits32);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(1)).unwrap();
+    // fork carries on in the child
+    sim.fork().unwrap();
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ).unwrap();
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ_WRITE).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(5)).unwrap();
+    sim.switch(0).unwrap();
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(1));
+}
+
+#[test]
+fn thirty_two_bit_paging_limits_frames() {
+    let config = SimulatorConfig::new().frames(1 << 20).paging(PagingMode::Bits32);
+    assert!(matches!(Simulator::begin(config), Err(SimError::InvalidConfig(_))));
+}
+
+#[test]
+fn fetching_from_a_no_execute_page_faults() {
+    let mut sim = start(PagingMode::Bits48);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(0x90)).unwrap();
+    let invalid = sim.stats().faults.invalid;
+    assert_eq!(sim.access(ptr, Access::Fetch), Err(SimError::ProtectionFault(ptr.vaddr())));
+    assert_eq!(sim.fetch(ptr), Err(SimError::ProtectionFault(ptr.vaddr())));
+    assert_eq!(sim.stats().faults.invalid, invalid + 2);
+    // reads and writes are still fine
+    sim.access(ptr, Access::Read).unwrap();
+    sim.access(ptr, Access::Write).unwrap();
+
+    // an I line in a trace is a fetch too
+    let text = format!("I {:x}\n", ptr.vaddr());
+    let trace = Trace::read(text.as_bytes(), Some(TraceFormat::Native)).unwrap();
+    match trace::replay(&mut sim, &trace) {
+        Err(TraceError::Sim { line: 1, err }) => assert_eq!(err, SimError::ProtectionFault(ptr.vaddr())),
+        other => panic!("fetch from a no-execute page gave {:?}", other.map(|r| r.accesses)),
+    }
+}
+
+#[test]
+fn read_write_exec_clears_no_execute() {
+    let mut sim = start(PagingMode::Bits32);
+    let ptr = page(&sim, 0);
+    sim.register(ptr).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(0x90)).unwrap();
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ_WRITE).unwrap();
+    assert_eq!(sim.fetch(ptr), Err(SimError::ProtectionFault(ptr.vaddr())));
+
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ_WRITE_EXEC).unwrap();
+    assert_eq!(sim.fetch(ptr).unwrap(), ValueType::UnsignedInt(0x90));
+    sim.access(ptr, Access::Fetch).unwrap();
+    sim.write(ptr, ValueType::UnsignedInt(0xc3)).unwrap();
+    assert_eq!(sim.fetch(ptr).unwrap(), ValueType::UnsignedInt(0xc3));
+
+    // and taking exec away sets it again
+    sim.protect(ptr.vaddr(), PAGE as usize, Prot::READ_WRITE).unwrap();
+    assert_eq!(sim.fetch(ptr), Err(SimError::ProtectionFault(ptr.vaddr())));
+}
@@ -0,0 +1,79 @@
+use rust_vmem::mem::mmap::Prot;
+use rust_vmem::sim::check::{Access, DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::pointer::Pointer;
+use rust_vmem::sim::trace::{self, Trace, TraceFormat};
+
+fn trace(text: &str) -> Trace {
+    Trace::read(text.as_bytes(), Some(TraceFormat::Native)).unwrap()
+}
+
+#[test]
+fn first_reads_fault_in_a_frame() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(16)).unwrap();
+    let report = trace::replay(&mut sim, &trace("R 80000000\nR 80001000\nR 80000008\nI 80002000\n")).unwrap();
+    assert_eq!(report.faults.zero_fill, 3);
+    assert_eq!(report.per_line.len(), 3);
+}
+
+#[test]
+fn a_swap_key_lets_a_trace_outgrow_memory() {
+    let text: String = (0..24).map(|n| format!("R {:x}\n", 0x80000000u64 + n * 4096)).collect();
+    let trace = trace(&text);
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    assert!(trace::replay(&mut sim, &trace).is_err());
+
+    let config = SimulatorConfig::parse("frames = 8\nswap = 32\n").unwrap();
+    assert_eq!(config.get_swap(), 32);
+    let mut sim = Simulator::begin(config).unwrap();
+    let report = trace::replay(&mut sim, &trace).unwrap();
+    assert_eq!(report.faults.zero_fill, 24);
+    assert!(report.evictions > 0);
+}
+
+#[test]
+fn touch_keeps_read_only_pages_read_only() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(16)).unwrap();
+    let ptr: Pointer<u64> = Pointer::from_addr(sim.config().get_base(), 0);
+    sim.register(ptr).unwrap();
+    sim.protect(ptr.vaddr(), 4096, Prot::READ).unwrap();
+    sim.touch(ptr, Access::Read).unwrap();
+    assert_eq!(sim.stats().faults.zero_fill, 1);
+    assert!(sim.write(ptr, ValueType::UnsignedInt(1)).is_err());
+    assert_eq!(sim.read(ptr, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(0));
+}
+
+#[test]
+fn the_report_shows_every_kind_of_fault() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(16)).unwrap();
+    let report = trace::replay(&mut sim, &trace("W 80000000\n")).unwrap();
+    let text = report.to_string();
+    for label in ["PgZeroFill", "PgCowCopy", "PgCowUpgrade", "PgSwapIn", "PgFileIn", "PgInvalid"] {
+        assert!(text.contains(label), "{} missing from\n{}", label, text);
+    }
+}
+
+const BELADY: &str = "W 1000\nW 2000\nW 3000\nW 4000\nW 1000\nW 2000\nW 5000\nW 1000\nW 2000\nW 3000\nW 4000\nW 5000\n";
+
+#[test]
+fn reads_count_towards_the_comparison() {
+    // three user frames after the zero page, directory and table
+    let config = SimulatorConfig::new().frames(6).swap(32).seed(42);
+    let faults = |text: &str| -> Vec<u64> {
+        let comparison = trace::compare(&config, &trace(text)).unwrap();
+        comparison.runs.iter().map(|run| run.report.faults.total()).collect()
+    };
+    let writes = faults(BELADY);
+    assert_eq!(writes[0], 9);
+    assert_eq!(*writes.last().unwrap(), 7);
+    assert_eq!(faults(&BELADY.replace('W', "R")), writes);
+}
+
+#[test]
+fn opt_takes_the_fewest_faults() {
+    let comparison = trace::compare(&SimulatorConfig::new().frames(7).swap(32), &trace(BELADY)).unwrap();
+    let opt = comparison.opt().unwrap().report.faults.total();
+    assert_eq!(opt, 6);
+    assert!(comparison.runs.iter().all(|run| run.report.faults.total() >= opt));
+    assert!(comparison.to_string().contains("PerRef"));
+}
@@ -0,0 +1,103 @@
+use rust_vmem::proc::proc::ProcessState;
+use rust_vmem::sim::check::{Access, Simulator};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::event::EventKind;
+use rust_vmem::sim::sched::{SchedPolicy, Scheduler};
+use rust_vmem::sim::trace::TraceOp;
+
+mod common;
+use common::PAGE;
+
+fn start() -> Simulator {
+    Simulator::begin(SimulatorConfig::new().frames(64)).unwrap()
+}
+
+// `n` reads of a page of its own for the process spawned `k`th.
+fn reads(k: u64, n: usize) -> Vec<TraceOp> {
+    let addr = SimulatorConfig::new().get_base() + k * PAGE;
+    vec![TraceOp::Access { access: Access::Read, addr, size: 8 }; n]
+}
+
+// The pids switched to, in order, after the first `skip` events.
+fn switches(sim: &Simulator, skip: usize) -> Vec<u32> {
+    sim.events()
+        .skip(skip)
+        .filter_map(|e| match e.kind {
+            EventKind::Switch { to } => Some(to),
+            _ => None
+        })
+        .collect()
+}
+
+#[test]
+fn round_robin_takes_turns_in_order() {
+    let mut sim = start();
+    let mut sched = Scheduler::new(SchedPolicy::RoundRobin, 2);
+    let a = sched.spawn(&mut sim, reads(0, 3), 0).unwrap();
+    let b = sched.spawn(&mut sim, reads(1, 4), 0).unwrap();
+    let c = sched.spawn(&mut sim, reads(2, 1), 0).unwrap();
+    let skip = sim.event_log().len();
+    let report = sched.run(&mut sim).unwrap();
+
+    assert_eq!(switches(&sim, skip), [a, b, c, a, b, 0]);
+    assert_eq!(report.ticks, 8);
+    assert_eq!(report.switches, 5);
+    let finished: Vec<(u32, u64, u64)> = report.tasks.iter().map(|t| (t.pid, t.finished, t.dispatches)).collect();
+    assert_eq!(finished, [(a, 6, 2), (b, 8, 2), (c, 5, 1)]);
+    assert_eq!(sim.current(), 0);
+}
+
+#[test]
+fn priority_runs_the_highest_first_and_shares_among_equals() {
+    let mut sim = start();
+    let mut sched = Scheduler::new(SchedPolicy::Priority, 1);
+    let low = sched.spawn(&mut sim, reads(0, 2), 1).unwrap();
+    let a = sched.spawn(&mut sim, reads(1, 2), 5).unwrap();
+    let b = sched.spawn(&mut sim, reads(2, 2), 5).unwrap();
+    let skip = sim.event_log().len();
+    let report = sched.run(&mut sim).unwrap();
+
+    assert_eq!(switches(&sim, skip), [a, b, a, b, low, 0]);
+    assert_eq!(report.tasks[0].finished, 6);
+    // the low priority process never had to give up the CPU once it got it
+    assert_eq!(report.tasks[0].dispatches, 1);
+}
+
+#[test]
+fn spawned_processes_sleep_until_their_turn() {
+    let mut sim = start();
+    let mut sched = Scheduler::new(SchedPolicy::RoundRobin, 1);
+    let a = sched.spawn(&mut sim, reads(0, 2), 0).unwrap();
+    let b = sched.spawn(&mut sim, reads(1, 2), 0).unwrap();
+    let state = |sim: &Simulator, pid: u32| sim.ps().procs.iter().find(|p| p.pid == pid).unwrap().state;
+    assert_eq!(state(&sim, a), ProcessState::Sleeping);
+    assert_eq!(state(&sim, b), ProcessState::Sleeping);
+    assert_eq!(state(&sim, 0), ProcessState::Running);
+
+    let report = sched.run(&mut sim).unwrap();
+    assert!(report.tasks.iter().all(|t| t.error.is_none() && t.ops == 2));
+    assert_eq!(state(&sim, a), ProcessState::Sleeping);
+    assert_eq!(state(&sim, b), ProcessState::Sleeping);
+    assert_eq!(state(&sim, 0), ProcessState::Running);
+}
+
+#[test]
+fn a_process_that_exited_is_skipped() {
+    let mut sim = start();
+    let mut sched = Scheduler::new(SchedPolicy::RoundRobin, 2);
+    let a = sched.spawn(&mut sim, reads(0, 3), 0).unwrap();
+    let b = sched.spawn(&mut sim, reads(1, 3), 0).unwrap();
+    sim.switch(a).unwrap();
+    sim.exit(0).unwrap();
+    assert_eq!(sim.current(), 0);
+
+    let skip = sim.event_log().len();
+    let report = sched.run(&mut sim).unwrap();
+    assert_eq!(switches(&sim, skip), [b, 0]);
+    assert_eq!(report.tasks[0].error, Some(SimError::NotRunning(a)));
+    assert_eq!(report.tasks[0].ops, 0);
+    assert_eq!(report.tasks[1].ops, 3);
+    assert_eq!(report.tasks[1].error, None);
+    assert_eq!(sim.current(), 0);
+}
@@ -0,0 +1,81 @@
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::error::SimError;
+use rust_vmem::sim::event::EventKind;
+
+mod common;
+use common::{page, TempFile};
+
+#[test]
+fn eviction_without_swap_runs_out_of_memory() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    let mut result = Ok(());
+    for n in 0..8 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        result = sim.write(ptr, ValueType::UnsignedInt(n as usize + 1));
+        if result.is_err() {
+            break;
+        }
+    }
+    assert_eq!(result, Err(SimError::OutOfMemory));
+    assert_eq!(sim.stats().evictions, 0);
+}
+
+#[test]
+fn swapped_out_pages_come_back_with_their_data() {
+    let swap = TempFile::new("round-trip", "swap");
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    sim.swapon(&swap, 16).unwrap();
+    for n in 0..12 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(n as usize + 1)).unwrap();
+    }
+    assert!(sim.stats().swap_outs > 0);
+
+    for n in 0..12 {
+        let value = sim.read(page(&sim, n), DataType::UnsignedInt).unwrap();
+        assert_eq!(value, ValueType::UnsignedInt(n as usize + 1));
+    }
+    assert!(sim.stats().faults.swap_in > 0);
+
+    // every frame a swap-in took was allocated like any other
+    let events: Vec<EventKind> = sim.events().map(|e| e.kind).collect();
+    for (i, kind) in events.iter().enumerate() {
+        if let EventKind::SwapIn { ppn, .. } = *kind {
+            let last = events[..i].iter().rev().find(|k| match k {
+                EventKind::Kalloc { ppn: p } | EventKind::Kfree { ppn: p } => *p == ppn,
+                _ => false
+            });
+            assert_eq!(last, Some(&EventKind::Kalloc { ppn }));
+        }
+    }
+}
+
+#[test]
+fn sharers_swap_in_to_the_same_frame() {
+    let swap = TempFile::new("shared", "swap");
+    let mut sim = Simulator::begin(SimulatorConfig::new().frames(8)).unwrap();
+    sim.swapon(&swap, 16).unwrap();
+    let shared = page(&sim, 0);
+    sim.register(shared).unwrap();
+    sim.write(shared, ValueType::UnsignedInt(42)).unwrap();
+    let child = sim.fork().unwrap();
+
+    // push the shared page out from the child
+    for n in 1..8 {
+        let ptr = page(&sim, n);
+        sim.register(ptr).unwrap();
+        sim.write(ptr, ValueType::UnsignedInt(n as usize)).unwrap();
+    }
+    assert_eq!(sim.read(shared, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(42));
+    sim.switch(0).unwrap();
+    assert_eq!(sim.read(shared, DataType::UnsignedInt).unwrap(), ValueType::UnsignedInt(42));
+    sim.switch(child).unwrap();
+
+    let ins = sim.events().filter(|e| e.kind.name() == "swap_in").count();
+    assert_eq!(ins, 2);
+    let copies = sim.events().filter(|e| e.kind.name() == "cow_copy").count();
+    assert_eq!(copies, 0);
+}
@@ -0,0 +1,37 @@
+use rust_vmem::sim::config::SimulatorConfig;
+use rust_vmem::sim::trace::{self, Trace, TraceFormat};
+
+const BELADY: &str = "W 1000\nW 2000\nW 3000\nW 4000\nW 1000\nW 2000\nW 5000\nW 1000\nW 2000\nW 3000\nW 4000\nW 5000\n";
+
+fn belady() -> Trace {
+    Trace::read(BELADY.as_bytes(), Some(TraceFormat::Native)).unwrap()
+}
+
+#[test]
+fn sweeps_over_user_frames() {
+    let sweep = trace::sweep(&SimulatorConfig::new().swap(32).seed(1), &belady(), 2..=5).unwrap();
+    let user: Vec<u32> = sweep.points.iter().map(|p| p.user_frames).collect();
+    assert_eq!(user, vec![2, 3, 4, 5]);
+    // the zero page, the directory and one table come on top
+    assert_eq!(sweep.points[0].frames, 5);
+
+    let fifo: Vec<(u32, u32)> = sweep.anomalies().iter()
+        .filter(|a| a.policy == "fifo")
+        .map(|a| (a.fewer_frames, a.frames))
+        .collect();
+    assert_eq!(fifo, vec![(3, 4)]);
+}
+
+#[test]
+fn random_is_never_an_anomaly() {
+    for seed in 0..8 {
+        let sweep = trace::sweep(&SimulatorConfig::new().swap(32).seed(seed), &belady(), 1..=6).unwrap();
+        assert!(sweep.anomalies().iter().all(|a| a.policy != "random"));
+    }
+}
+
+#[test]
+fn frame_counts_start_at_one() {
+    assert_eq!(trace::parse_frames("3-5"), Some(vec![3, 4, 5]));
+    assert_eq!(trace::parse_frames("0-4"), None);
+}
@@ -0,0 +1,72 @@
+use rust_vmem::mem::mmap::Prot;
+use rust_vmem::mem::replace::PolicyKind;
+use rust_vmem::sim::check::{DataType, Simulator, ValueType};
+use rust_vmem::sim::config::SimulatorConfig;
+
+mod common;
+use common::{page, TempFile, PAGE};
+
+// TLB misses taken by reading page `n`.
+fn misses(sim: &mut Simulator, n: u64) -> u64 {
+    let before = sim.stats().tlb.misses;
+    sim.read(page(sim, n), DataType::UnsignedInt).unwrap();
+    sim.stats().tlb.misses - before
+}
+
+#[test]
+fn a_cached_page_hits() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    sim.register(page(&sim, 0)).unwrap();
+    sim.write(page(&sim, 0), ValueType::UnsignedInt(1)).unwrap();
+    assert_eq!(misses(&mut sim, 0), 0);
+}
+
+#[test]
+fn sampling_the_working_set_invalidates_what_it_clears() {
+    let mut sim = Simulator::begin(SimulatorConfig::new().working_set(10, 1)).unwrap();
+    sim.register(page(&sim, 0)).unwrap();
+    sim.write(page(&sim, 0), ValueType::UnsignedInt(1)).unwrap();
+    // the sample after the write cleared the accessed bit
+    assert_eq!(misses(&mut sim, 0), 1);
+    assert_eq!(misses(&mut sim, 0), 1);
+}
+
+#[test]
+fn the_clock_hand_invalidates_what_it_clears() {
+    let swap = TempFile::new("clock", "swap");
+    let config = SimulatorConfig::new().frames(8).policy(PolicyKind::Clock);
+    let mut sim = Simulator::begin(config).unwrap();
+    sim.swapon(&swap, 16).unwrap();
+    // the directory and a page table leave room for five pages
+    for n in 0..5 {
+        sim.register(page(&sim, n)).unwrap();
+        sim.write(page(&sim, n), ValueType::UnsignedInt(1)).unwrap();
+    }
+    assert_eq!(misses(&mut sim, 4), 0);
+
+    // every page was referenced, so the hand clears them all on its way
+    // round before it evicts the first
+    sim.register(page(&sim, 5)).unwrap();
+    sim.write(page(&sim, 5), ValueType::UnsignedInt(1)).unwrap();
+    assert_eq!(sim.stats().evictions, 1);
+    assert_eq!(misses(&mut sim, 4), 1);
+}
+
+#[test]
+fn only_cached_pages_count_as_invalidated() {
+    let mut sim = Simulator::begin(SimulatorConfig::new()).unwrap();
+    for n in 0..3 {
+        sim.register(page(&sim, n)).unwrap();
+    }
+    // nothing was touched, so nothing is cached
+    let base = page(&sim, 0).vaddr();
+    sim.protect(base, PAGE as usize, Prot::READ).unwrap();
+    sim.munmap(base, PAGE as usize).unwrap();
+    assert_eq!(sim.stats().tlb.invalidations, 0);
+    assert_eq!(sim.machine().mem().tlb().stats_for(0).invalidations, 0);
+
+    sim.read(page(&sim, 1), DataType::UnsignedInt).unwrap();
+    sim.munmap(base + PAGE, 2 * PAGE as usize).unwrap();
+    assert_eq!(sim.stats().tlb.invalidations, 1);
+    assert_eq!(sim.machine().mem().tlb().stats_for(0).invalidations, 1);
+}
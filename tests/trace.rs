@@ -0,0 +1,83 @@
+use rust_vmem::sim::check::Access;
+use rust_vmem::sim::trace::{Trace, TraceError, TraceFormat, TraceOp};
+
+fn at(access: Access, addr: u64, size: u64) -> TraceOp {
+    TraceOp::Access { access, addr, size }
+}
+
+#[test]
+fn native_lines() {
+    let native = TraceFormat::Native;
+    assert_eq!(native.parse("R 80000000"), Some(vec![at(Access::Read, 0x80000000, 1)]));
+    assert_eq!(native.parse("W 0x1000 8  # a store"), Some(vec![at(Access::Write, 0x1000, 8)]));
+    assert_eq!(native.parse("I 400000 4"), Some(vec![at(Access::Fetch, 0x400000, 4)]));
+    assert_eq!(native.parse("F"), Some(vec![TraceOp::Fork]));
+    assert_eq!(native.parse("S 3"), Some(vec![TraceOp::Switch(3)]));
+    assert_eq!(native.parse("# only a comment"), Some(vec![]));
+    assert_eq!(native.parse("X 1000"), None);
+    assert_eq!(native.parse("R 1000 8 9"), None);
+    assert_eq!(native.parse("R zz"), None);
+}
+
+#[test]
+fn lackey_lines() {
+    let lackey = TraceFormat::Lackey;
+    assert_eq!(lackey.parse("I  04016a0,3"), Some(vec![at(Access::Fetch, 0x4016a0, 3)]));
+    assert_eq!(lackey.parse(" L 7ff000a10,8"), Some(vec![at(Access::Read, 0x7ff000a10, 8)]));
+    assert_eq!(lackey.parse(" S 7ff000a18,8"), Some(vec![at(Access::Write, 0x7ff000a18, 8)]));
+    // a modify is a read then a write of the same bytes
+    assert_eq!(
+        lackey.parse(" M 0421c7f0,4"),
+        Some(vec![at(Access::Read, 0x421c7f0, 4), at(Access::Write, 0x421c7f0, 4)])
+    );
+    assert_eq!(lackey.parse("==12345== Lackey, an example Valgrind tool"), Some(vec![]));
+    assert_eq!(lackey.parse(""), Some(vec![]));
+    assert_eq!(lackey.parse(" X 1000,4"), None);
+    assert_eq!(lackey.parse(" L 1000"), None);
+}
+
+#[test]
+fn dinero_lines() {
+    let din = TraceFormat::Dinero;
+    assert_eq!(din.parse("0 1000"), Some(vec![at(Access::Read, 0x1000, 1)]));
+    assert_eq!(din.parse("1 2000 8"), Some(vec![at(Access::Write, 0x2000, 8)]));
+    assert_eq!(din.parse("2 0x400000"), Some(vec![at(Access::Fetch, 0x400000, 1)]));
+    // escapes for the cache simulator are no references
+    assert_eq!(din.parse("3 0"), Some(vec![]));
+    assert_eq!(din.parse("4 1000"), Some(vec![]));
+    assert_eq!(din.parse("5 1000"), None);
+    assert_eq!(din.parse("0 1000 big"), None);
+}
+
+#[test]
+fn detect_picks_the_format_that_reads_the_line() {
+    assert_eq!(TraceFormat::detect(" S 7ff000a18,8"), Some(TraceFormat::Lackey));
+    assert_eq!(TraceFormat::detect("1 2000"), Some(TraceFormat::Dinero));
+    assert_eq!(TraceFormat::detect("W 2000"), Some(TraceFormat::Native));
+    assert_eq!(TraceFormat::detect("S 2"), Some(TraceFormat::Native));
+    // a comma in a native comment does not make it lackey
+    assert_eq!(TraceFormat::detect("R 2000 # a, b"), Some(TraceFormat::Native));
+    // lines any format could hold decide nothing
+    for line in ["", "# comment", "==1== valgrind", "3 0"] {
+        assert_eq!(TraceFormat::detect(line), None, "{:?}", line);
+    }
+    // a line no format reads is left to native to report
+    assert_eq!(TraceFormat::detect("7 2000"), Some(TraceFormat::Native));
+}
+
+#[test]
+fn reading_skips_what_precedes_the_first_reference() {
+    let text = "==1== Lackey\n==1== Copyright\n I  04016a0,3\n M 0421c7f0,4\n";
+    let trace = Trace::read(text.as_bytes(), None).unwrap();
+    assert_eq!(trace.lines(), 4);
+    let ops: Vec<TraceOp> = trace.ops().collect();
+    assert_eq!(ops.len(), 3);
+
+    let trace = Trace::read("3 0\n0 1000\n2 2000\n".as_bytes(), None).unwrap();
+    assert_eq!(trace.ops().count(), 2);
+
+    match Trace::read("R 1000\n9 2000\n".as_bytes(), None) {
+        Err(TraceError::Syntax { line: 2, .. }) => {},
+        other => panic!("a bad line gave {:?}", other.map(|t| t.lines())),
+    }
+}